@@ -24,24 +24,23 @@ fn main() {
         }
         println!("----------------------------------------\n");
 
-        println!("{} Labels", parser.labels.len());
+        println!("{} Labels", parser.get_label_names().count());
         println!("- - - - - - - - - - ");
 
-        for l in parser.labels.iter() {
+        for l in parser.get_label_names() {
             println!("{:?}", l);
         }
         println!("----------------------------------------\n");
 
-        println!("{} Problems", parser.problems.len());
+        println!("{} Problems", parser.get_problems().count());
         println!("- - - - - - - - - - ");
 
-        for p in parser.problems.iter() {
+        for p in parser.get_problems() {
             match p {
                 Problem::MissingCallee(c, range) => {
                     match parser
-                        .labels
-                        .iter()
-                        .find(|(l, _)| parser.get_element_bytes(range) == **l)
+                        .get_label_names()
+                        .find(|l| *parser.get_element_bytes(range) == ***l)
                     {
                         None => println!("{:?}, {}", p, parser.get_element_str(range)),
                         Some(_) => {}
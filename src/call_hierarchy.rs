@@ -0,0 +1,62 @@
+use tower_lsp::lsp_types::{CallHierarchyItem, Position, Range, SymbolKind, Url};
+
+use crate::{
+    inlay_hints::ExecCalls,
+    parser::{Expression, Macro, Parser},
+};
+
+/// the call hierarchy item of the macro `m`
+pub fn get_macro_item(m: &Macro, parser: &Parser, uri: &Url) -> CallHierarchyItem {
+    CallHierarchyItem {
+        name: parser.get_element_str(&m.name),
+        kind: SymbolKind::FUNCTION,
+        tags: None,
+        detail: Some(parser.get_element_str(&(m.parenopen, m.parenclose + 1))),
+        uri: uri.clone(),
+        range: parser.lexer.range_to_text_range(m),
+        selection_range: parser.lexer.range_to_text_range(&m.name),
+        data: None,
+    }
+}
+
+/// the call hierarchy item standing for the top-level statements (everything outside of macros)
+/// of a document
+pub fn get_file_item(parser: &Parser, uri: &Url) -> CallHierarchyItem {
+    let start = Position::default();
+    let end = Position::new(parser.lexer.line_count() as u32, 0);
+    CallHierarchyItem {
+        name: uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or(uri.as_str())
+            .to_string(),
+        kind: SymbolKind::FILE,
+        tags: None,
+        detail: None,
+        uri: uri.clone(),
+        range: Range::new(start, end),
+        selection_range: Range::new(start, start),
+        data: None,
+    }
+}
+
+/// collects the exec calls in `expressions` and groups the ranges of the calls by their
+/// (lowercase) callee
+pub fn get_calls<'a, I: IntoIterator<Item = &'a Expression>>(
+    expressions: I,
+    parser: &Parser,
+) -> Vec<(Vec<u8>, Vec<Range>)> {
+    let mut exec_calls = ExecCalls::new(parser);
+    for e in expressions {
+        e.accept(&mut exec_calls);
+    }
+
+    let mut calls: Vec<(Vec<u8>, Vec<Range>)> = Vec::new();
+    for call in exec_calls.calls {
+        match calls.iter_mut().find(|(callee, _)| *callee == call.callee) {
+            Some((_, ranges)) => ranges.push(call.callee_range),
+            None => calls.push((call.callee, vec![call.callee_range])),
+        }
+    }
+    calls
+}
@@ -1,42 +1,99 @@
+use std::borrow::Cow;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::{
-    CompletionItem, CompletionItemKind, DocumentHighlight, MarkedString, Position, Range,
-    SemanticTokens, SemanticTokensResult, Url,
+    CallHierarchyIncomingCall, CallHierarchyItem, CompletionItem, CompletionItemKind,
+    DocumentHighlight, DocumentHighlightKind, DocumentLink, DocumentSymbol, FoldingRange, Location,
+    MarkedString, Position, Range, SelectionRange, SemanticToken, SemanticTokens,
+    SemanticTokensDelta, SemanticTokensFullDeltaResult, SemanticTokensRangeResult,
+    SemanticTokensResult, SignatureInformation, SymbolInformation, TypeHierarchyItem, Url,
 };
 
+use crate::call_hierarchy::{get_calls, get_file_item, get_macro_item};
+use crate::document_links::get_document_links;
+use crate::document_symbols::get_workspace_symbols;
 use crate::error::UTF8_PARSER_MSG;
-use crate::lexer::HasRange;
+use crate::folding_ranges::get_comment_folding_ranges;
+use crate::inlay_hints::{ExecCall, ExecCalls};
+use crate::lexer::{CursorPosition, HasRange, Token};
 use crate::parser::{Expression, MaybeProblem, Parser, Problem, GENERIC_BUILTINS};
+use crate::semantic_tokens::get_semantic_token_edits;
+use crate::signature_help::{get_call_context, CallContext};
+use crate::type_hierarchy::{get_attributes, get_element_item};
+
+/// every (re)load of a document gets a new revision, unique over all documents
+static NEXT_REVISION: AtomicUsize = AtomicUsize::new(0);
+
+fn next_revision() -> usize {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug)]
 pub struct Document {
     pub parser: Parser,
+    revision: usize,
+    /// the semantic tokens last sent to the client, delta requests are answered against them
+    semantic_tokens: Option<SemanticTokens>,
 }
 
 impl Document {
     pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        Ok(Self {
-            parser: Parser::from_path(path)?,
-        })
+        Ok(Self::new(None, &std::fs::read(path)?))
     }
 
     pub fn new(uri: Option<Url>, text: &[u8]) -> Self {
         Self {
             parser: Parser::from_bytes(text.to_vec(), uri),
+            revision: next_revision(),
+            semantic_tokens: None,
         }
     }
 
     pub fn reload(&mut self, text: &[u8]) {
         let uri = self.parser.uri.clone();
         self.parser = Parser::from_bytes(text.to_vec(), uri);
+        self.revision = next_revision();
         //self.parser.scan_includes();
     }
 
+    /// replaces `range` by `text`, only the statements touched by the edit are parsed again
+    pub fn edit(&mut self, range: Range, text: &str) {
+        let start = self.char_index(range.start);
+        let end = self.char_index(range.end).max(start);
+        let start_byte = self.parser.lexer.get_text().char_to_byte(start);
+        let end_byte = self.parser.lexer.get_text().char_to_byte(end);
+
+        self.parser.edit(start_byte, end_byte, text);
+        self.revision = next_revision();
+    }
+
+    /// converts an lsp position to a char index into the text, positions behind the end of a line
+    /// are moved to the end of the line
+    fn char_index(&self, pos: Position) -> usize {
+        let text = self.parser.lexer.get_text();
+        let line = pos.line as usize;
+        if line >= text.len_lines() {
+            return text.len_chars();
+        }
+        let line_text = text.line(line);
+        let mut line_len = line_text.len_chars();
+        while line_len > 0 && matches!(line_text.char(line_len - 1), '\n' | '\r') {
+            line_len -= 1;
+        }
+        let character = (pos.character as usize).min(line_text.char_to_utf16_cu(line_len));
+        text.line_to_char(line) + line_text.utf16_cu_to_char(character)
+    }
+
+    /// changes whenever the document is reloaded
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
     pub fn get_diagnostics(&self) -> Vec<MaybeProblem> {
         log::debug!("parser.problems:");
-        for p in self.parser.problems.iter() {
+        for p in self.parser.get_problems() {
             match p {
                 Problem::MissingCallee(_, range) => {
                     log::debug!("MissingCalle: {}", self.parser.get_element_str(range))
@@ -51,16 +108,9 @@ impl Document {
         }
 
         self.parser
-            .problems
-            .iter()
+            .get_problems()
             .map(|p| {
-                let range = match p {
-                    crate::parser::Problem::MissingCallee(_, range) => range,
-                    crate::parser::Problem::InvalidParam(range) => range,
-                    crate::parser::Problem::Error(_, _, _) => todo!(),
-                    crate::parser::Problem::Warning(_, _, _) => todo!(),
-                    crate::parser::Problem::Hint(_, _, _) => todo!(),
-                };
+                let range = p.get_range();
                 MaybeProblem {
                     problem: Some(p.clone()),
                     range: Range::new(
@@ -72,52 +122,153 @@ impl Document {
             .collect()
     }
 
+    /// highlights the symbol under the cursor in this document, definitions and assignment
+    /// targets are marked as writes, all other usages as reads
     pub fn get_document_highlights(
         &self,
         pos: &Position,
     ) -> Result<Option<Vec<DocumentHighlight>>> {
+        let Some(name) = self.get_symbol_under_cursor(*pos) else {
+            return Ok(None);
+        };
         let position = self.parser.lexer.cursor_pos_from_text_pos(*pos);
-        if let Some(exp) = self.parser.get_expression_at(position) {
-            let highlights = exp.get_highlights(&position, &self.parser);
 
-            return Ok(Some(
-                highlights
-                    .iter()
-                    .map(|range| DocumentHighlight {
-                        range: Range::new(
-                            self.parser.lexer.cursor_pos_to_text_pos(range.0),
-                            self.parser.lexer.cursor_pos_to_text_pos(range.1),
-                        ),
-                        kind: None,
-                    })
-                    .collect(),
-            ));
+        let mut writes = Vec::new();
+        let mut reads = Vec::new();
+        // symbols local to an expression (macro arguments) are only highlighted in there
+        let local = self
+            .parser
+            .get_expressions_at(position)
+            .into_iter()
+            .rev()
+            .map(|exp| (exp, exp.get_highlights(&position, &self.parser)))
+            .find(|(_, ranges)| !ranges.is_empty());
+        if let Some((exp, ranges)) = local {
+            // the expression itself shadows the symbol, only its bodies can assign it
+            let mut declarations = Vec::new();
+            for e in exp.get_bodies() {
+                e.get_declarations(&name, &self.parser, &mut declarations);
+            }
+            // the first range is where the local symbol is declared
+            declarations.push(ranges[0]);
+            (writes, reads) = ranges
+                .into_iter()
+                .partition(|range| declarations.contains(range));
+        } else {
+            for e in self.parser.get_elements() {
+                e.get_declarations(&name, &self.parser, &mut writes);
+                e.get_references(&name, &self.parser, &mut reads);
+            }
         }
-        Ok(None)
+
+        let mut highlights = writes
+            .iter()
+            .map(|range| (range, DocumentHighlightKind::WRITE))
+            .chain(
+                reads
+                    .iter()
+                    .map(|range| (range, DocumentHighlightKind::READ)),
+            )
+            .map(|(range, kind)| DocumentHighlight {
+                range: self.parser.lexer.range_to_text_range(range),
+                kind: Some(kind),
+            })
+            .collect::<Vec<_>>();
+        highlights.sort_by_key(|h| h.range.start);
+        Ok(Some(highlights))
     }
 
-    pub fn get_semantic_tokens(&self) -> Result<Option<SemanticTokensResult>> {
+    /// the semantic tokens of `elements`, up to the first `exit`
+    fn get_semantic_token_data<'a>(
+        &self,
+        elements: impl Iterator<Item = &'a Expression>,
+    ) -> Vec<SemanticToken> {
         let mut pre_line = 0;
         let mut pre_start = 0;
         //log::info!("{:#?}", parser.get_elements());
-        log::info!("parser elements: {}", self.parser.get_elements().len());
         let mut data = Vec::new();
-        for e in self.parser.get_elements() {
+        for e in elements {
             e.to_semantic_token(&mut data, &mut pre_line, &mut pre_start, &self.parser);
             if let Expression::Exit(_) = e {
                 break;
             }
         }
         //log::info!("data: {:#?}", data);
+        data
+    }
+
+    /// computes the semantic tokens of the whole document and keeps them as the ones the client
+    /// has, returns the ones it had before
+    fn send_semantic_tokens(&mut self) -> (Option<SemanticTokens>, SemanticTokens) {
+        let tokens = SemanticTokens {
+            result_id: Some(self.revision.to_string()),
+            data: self.get_semantic_token_data(self.parser.get_elements().iter()),
+        };
+        (self.semantic_tokens.replace(tokens.clone()), tokens)
+    }
+
+    pub fn get_semantic_tokens(&mut self) -> Result<Option<SemanticTokensResult>> {
+        let (_, tokens) = self.send_semantic_tokens();
+        Ok(Some(SemanticTokensResult::Tokens(tokens)))
+    }
+
+    /// the changes to the semantic tokens sent with `previous_result_id`, all tokens if those are
+    /// not the last ones sent
+    pub fn get_semantic_tokens_delta(
+        &mut self,
+        previous_result_id: &str,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let (previous, tokens) = self.send_semantic_tokens();
+        match previous.filter(|p| p.result_id.as_deref() == Some(previous_result_id)) {
+            Some(previous) => Ok(Some(SemanticTokensFullDeltaResult::TokensDelta(
+                SemanticTokensDelta {
+                    result_id: tokens.result_id,
+                    edits: get_semantic_token_edits(&previous.data, &tokens.data),
+                },
+            ))),
+            None => Ok(Some(SemanticTokensFullDeltaResult::Tokens(tokens))),
+        }
+    }
+
+    /// the semantic tokens of the elements overlapping `range`
+    pub fn get_semantic_tokens_range(
+        &self,
+        range: Range,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let elements = self.parser.get_elements();
+        let lines = self.parser.lexer.line_count() as u32;
+        let first = if range.start.line < lines {
+            let start = self.parser.lexer.cursor_pos_from_text_pos(range.start);
+            elements.partition_point(|e| e.get_range().1 <= start)
+        } else {
+            elements.len()
+        };
+        let last = if range.end.line < lines {
+            let end = self.parser.lexer.cursor_pos_from_text_pos(range.end);
+            elements
+                .partition_point(|e| e.get_range().0 < end)
+                .max(first)
+        } else {
+            elements.len()
+        };
 
-        let tokens = Some(SemanticTokensResult::Tokens(SemanticTokens {
+        // nothing behind an `exit` is highlighted
+        let data = if elements
+            .iter()
+            .take(first)
+            .any(|e| matches!(e, Expression::Exit(_)))
+        {
+            Vec::new()
+        } else {
+            self.get_semantic_token_data(elements.iter().skip(first).take(last - first))
+        };
+        Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+            result_id: None,
             data,
-            ..Default::default()
-        }));
-        Ok(tokens)
+        })))
     }
 
-    pub fn get_labels_under_cursor(&self, position: Position) -> Vec<&[u8]> {
+    pub fn get_labels_under_cursor(&self, position: Position) -> Vec<Cow<'_, [u8]>> {
         let pos = self.parser.lexer.cursor_pos_from_text_pos(position);
 
         self.parser
@@ -130,26 +281,26 @@ impl Document {
     /// gets the hover information for the given set of labels
     pub fn get_hover(
         &self,
-        labels: &Vec<&[u8]>,
+        labels: &[Cow<[u8]>],
         items: &mut Vec<MarkedString>,
         infile: Option<&Url>,
     ) {
         for label in labels.iter() {
             // first, look in named labels
-            if let Some(index) = self.parser.labels.get(*label) {
-                let comment = if *index > 0 {
+            if let Some(index) = self.parser.get_label_index(label) {
+                let comment = if index > 0 {
                     sanitize_string_for_md(
                         self.parser
-                            .get_element_str(&self.parser.get_elements()[*index - 1])
+                            .get_element_str(&self.parser.get_elements()[index - 1])
                             .to_string(),
                     )
                 } else {
                     String::new()
                 };
 
-                let element = &self.parser.get_elements()[*index];
+                let element = &self.parser.get_elements()[index];
 
-                let line = element.get_range().0.line();
+                let line = self.parser.lexer.line(&element.get_range().0);
                 let location = match infile {
                     Some(uri) => {
                         format!("\"{}\", ", uri.path())
@@ -181,9 +332,13 @@ impl Document {
                     Expression::MadEnvironment(_) => todo!(),
                     Expression::Exit(_) => todo!(),
                     Expression::Operator(_) => todo!(),
+                    Expression::Group(_) => todo!(),
+                    Expression::Call(_) => todo!(),
+                    Expression::Array(_) => todo!(),
                     Expression::TokenExp(_) => todo!(),
                     Expression::Exec(_) => todo!(),
                     Expression::If(_) => todo!(),
+                    Expression::While(_) => todo!(),
                     Expression::Noop(_) => todo!(),
                 };
 
@@ -195,9 +350,327 @@ impl Document {
         }
     }
 
+    /// gets the locations of the definitions of the given set of labels in this document
+    pub fn get_definitions(&self, labels: &[Cow<[u8]>], uri: &Url) -> Vec<Location> {
+        labels
+            .iter()
+            .filter_map(|label| self.parser.get_label_index(&label.to_ascii_lowercase()))
+            .filter_map(|index| self.parser.get_elements()[index].get_name_range())
+            .map(|range| Location::new(uri.clone(), self.parser.lexer.range_to_text_range(&range)))
+            .collect()
+    }
+
+    /// gets the (lowercase) name of the identifier under the cursor
+    pub fn get_symbol_under_cursor(&self, position: Position) -> Option<Vec<u8>> {
+        let pos = self.parser.lexer.cursor_pos_from_text_pos(position);
+        match self.parser.get_token_at(pos) {
+            Some(token @ Token::Ident(_)) => {
+                Some(self.parser.get_element_bytes(token).to_ascii_lowercase())
+            }
+            _ => None,
+        }
+    }
+
+    /// the declaration and the usages of the macro argument at `position`, if there is one.
+    /// Arguments are local to their macro.
+    pub fn get_macro_arg_ranges(&self, position: Position) -> Option<Vec<Range>> {
+        let pos = self.parser.lexer.cursor_pos_from_text_pos(position);
+        let m = self
+            .parser
+            .get_expressions_at(pos)
+            .into_iter()
+            .rev()
+            .find_map(|exp| match exp {
+                Expression::Macro(m) => Some(m),
+                _ => None,
+            })?;
+        let ranges = m.get_arg_ranges(&pos, &self.parser);
+        if ranges.is_empty() {
+            return None;
+        }
+        Some(
+            ranges
+                .iter()
+                .map(|range| self.parser.lexer.range_to_text_range(range))
+                .collect(),
+        )
+    }
+
+    /// gets the locations of all usages of the symbol `name` (lowercase) in this document.
+    /// With `include_declaration`, the definitions are listed as well.
+    pub fn get_references(
+        &self,
+        name: &[u8],
+        include_declaration: bool,
+        uri: &Url,
+    ) -> Vec<Location> {
+        self.get_reference_ranges(name, include_declaration)
+            .iter()
+            .map(|range| Location::new(uri.clone(), self.parser.lexer.range_to_text_range(range)))
+            .collect()
+    }
+
+    /// counts the usages of the symbol `name` (lowercase) in this document
+    pub fn count_references(&self, name: &[u8]) -> usize {
+        self.get_reference_ranges(name, false).len()
+    }
+
+    fn get_reference_ranges(
+        &self,
+        name: &[u8],
+        include_declaration: bool,
+    ) -> Vec<(CursorPosition, CursorPosition)> {
+        let mut ranges = Vec::new();
+        for e in self.parser.get_elements() {
+            if include_declaration {
+                e.get_declarations(name, &self.parser, &mut ranges);
+            }
+            e.get_references(name, &self.parser, &mut ranges);
+        }
+        ranges
+    }
+
+    /// gets the names (lowercase) and name ranges of the top-level definitions
+    /// (macros, labels and variables), in order of appearance
+    pub fn get_top_level_definitions(&self) -> Vec<(Vec<u8>, Range)> {
+        let mut definitions = self
+            .parser
+            .get_label_names()
+            .filter_map(|name| {
+                let index = self.parser.get_label_index(name)?;
+                let range = self.parser.get_elements()[index].get_name_range()?;
+                Some((name.clone(), self.parser.lexer.range_to_text_range(&range)))
+            })
+            .collect::<Vec<_>>();
+        definitions.sort_by_key(|(_, range)| range.start);
+        definitions
+    }
+
+    pub fn get_document_symbols(&self) -> Vec<DocumentSymbol> {
+        self.parser
+            .get_elements()
+            .iter()
+            .filter_map(|e| e.get_document_symbol(&self.parser))
+            .collect()
+    }
+
+    /// gets all definitions in this document that fuzzy-match `query`, together with their score
+    pub fn get_workspace_symbols(&self, query: &str, uri: &Url) -> Vec<(usize, SymbolInformation)> {
+        let mut definitions = Vec::new();
+        for e in self.parser.get_elements() {
+            e.get_definitions(None, &mut definitions);
+        }
+        let mut matches = Vec::new();
+        get_workspace_symbols(&definitions, &self.parser, query, uri, &mut matches);
+        matches
+    }
+
+    /// gets the selection ranges at `positions`, growing from the token under the cursor to the
+    /// top-level expression containing it
+    pub fn get_selection_ranges(&self, positions: &[Position]) -> Vec<SelectionRange> {
+        positions
+            .iter()
+            .map(|position| {
+                let pos = self.parser.lexer.cursor_pos_from_text_pos(*position);
+                let mut ranges = Vec::new();
+                for e in self.parser.get_elements() {
+                    e.get_selection_ranges(pos, &mut ranges);
+                }
+                if let Some(token) = self.parser.get_token_at(pos) {
+                    ranges.push(token.get_range());
+                }
+
+                // each range has to contain the next one, this also skips expressions that
+                // merely touch the cursor after one containing it has been found
+                let mut nested: Vec<(CursorPosition, CursorPosition)> = Vec::new();
+                for range in ranges {
+                    if nested
+                        .last()
+                        .is_none_or(|last| last.0 <= range.0 && range.1 <= last.1 && *last != range)
+                    {
+                        nested.push(range);
+                    }
+                }
+
+                nested
+                    .iter()
+                    .fold(None, |parent, range| {
+                        Some(SelectionRange {
+                            range: self.parser.lexer.range_to_text_range(range),
+                            parent: parent.map(Box::new),
+                        })
+                    })
+                    .unwrap_or(SelectionRange {
+                        range: Range::new(*position, *position),
+                        parent: None,
+                    })
+            })
+            .collect()
+    }
+
+    /// gets the foldable blocks and comment sections of this document
+    pub fn get_folding_ranges(&self) -> Vec<FoldingRange> {
+        let mut ranges = Vec::new();
+        for e in self.parser.get_elements() {
+            e.get_folding_ranges(&mut ranges, &self.parser);
+        }
+        get_comment_folding_ranges(&self.parser, &mut ranges);
+        ranges
+    }
+
+    /// gets the macro call or builtin command the cursor is in
+    pub fn get_call_context(&self, position: Position) -> Option<CallContext> {
+        get_call_context(
+            &self.parser,
+            self.parser.lexer.cursor_pos_from_text_pos(position),
+        )
+    }
+
+    /// gets the signature of the macro `name` (lowercase), if it's defined in this document
+    pub fn get_macro_signature(&self, name: &[u8], active: u32) -> Option<SignatureInformation> {
+        match &self.parser.get_elements()[self.parser.get_label_index(name)?] {
+            Expression::Macro(m) => Some(m.get_signature(active, &self.parser)),
+            _ => None,
+        }
+    }
+
+    /// gets the parameter names of the macro `name` (lowercase), if it's defined in this document
+    pub fn get_macro_args(&self, name: &[u8]) -> Option<Vec<String>> {
+        match &self.parser.get_elements()[self.parser.get_label_index(name)?] {
+            Expression::Macro(m) => Some(
+                m.args
+                    .iter()
+                    .map(|arg| self.parser.get_element_str(arg))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// gets the call hierarchy item of the macro `name` (lowercase), if it's defined in this
+    /// document
+    pub fn get_call_hierarchy_item(&self, name: &[u8], uri: &Url) -> Option<CallHierarchyItem> {
+        match &self.parser.get_elements()[self.parser.get_label_index(name)?] {
+            Expression::Macro(m) => Some(get_macro_item(m, &self.parser, uri)),
+            _ => None,
+        }
+    }
+
+    /// gets the macros called by the macro `name` (lowercase), together with the ranges of the
+    /// calls
+    pub fn get_outgoing_calls(&self, name: &[u8]) -> Vec<(Vec<u8>, Vec<Range>)> {
+        match self
+            .parser
+            .get_label_index(name)
+            .map(|index| &self.parser.get_elements()[index])
+        {
+            Some(Expression::Macro(m)) => get_calls(&m.body, &self.parser),
+            _ => Vec::new(),
+        }
+    }
+
+    /// gets the macros called outside of macro definitions, together with the ranges of the calls
+    pub fn get_top_level_calls(&self) -> Vec<(Vec<u8>, Vec<Range>)> {
+        get_calls(
+            self.parser
+                .get_elements()
+                .iter()
+                .filter(|e| !matches!(e, Expression::Macro(_))),
+            &self.parser,
+        )
+    }
+
+    /// gets the callers of the macro `name` (lowercase) in this document. Calls outside of macro
+    /// definitions are attributed to the document itself.
+    pub fn get_incoming_calls(&self, name: &[u8], uri: &Url) -> Vec<CallHierarchyIncomingCall> {
+        let called_from = |calls: Vec<(Vec<u8>, Vec<Range>)>| {
+            calls
+                .into_iter()
+                .find(|(callee, _)| callee == name)
+                .map(|(_, from_ranges)| from_ranges)
+        };
+
+        let mut incoming = Vec::new();
+        for e in self.parser.get_elements() {
+            if let Expression::Macro(m) = e {
+                if let Some(from_ranges) = called_from(get_calls(&m.body, &self.parser)) {
+                    incoming.push(CallHierarchyIncomingCall {
+                        from: get_macro_item(m, &self.parser, uri),
+                        from_ranges,
+                    });
+                }
+            }
+        }
+        if let Some(from_ranges) = called_from(self.get_top_level_calls()) {
+            incoming.push(CallHierarchyIncomingCall {
+                from: get_file_item(&self.parser, uri),
+                from_ranges,
+            });
+        }
+        incoming
+    }
+
+    /// gets the definition `name: class, attributes...;` of the element `name` (lowercase): the
+    /// (lowercase) class, the range of the class name and the attributes set in the definition
+    pub fn get_element_definition(&self, name: &[u8]) -> Option<(Vec<u8>, Range, Vec<String>)> {
+        match &self.parser.get_elements()[self.parser.get_label_index(name)?] {
+            Expression::Label(l) => Some((
+                l.get_class(&self.parser),
+                self.parser.lexer.range_to_text_range(&l.command.name),
+                get_attributes(&l.command.args, &self.parser),
+            )),
+            _ => None,
+        }
+    }
+
+    /// gets the type hierarchy item of the element `name` (lowercase), if it's defined in this
+    /// document
+    pub fn get_type_hierarchy_item(&self, name: &[u8], uri: &Url) -> Option<TypeHierarchyItem> {
+        match &self.parser.get_elements()[self.parser.get_label_index(name)?] {
+            Expression::Label(l) => Some(get_element_item(l, &self.parser, uri)),
+            _ => None,
+        }
+    }
+
+    /// gets the elements of this document that are derived from the class `name` (lowercase)
+    pub fn get_subtypes(&self, name: &[u8], uri: &Url) -> Vec<TypeHierarchyItem> {
+        self.parser
+            .get_elements()
+            .iter()
+            .filter_map(|e| match e {
+                Expression::Label(l) if l.get_class(&self.parser) == name => {
+                    Some(get_element_item(l, &self.parser, uri))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// gets the exec calls of this document that have arguments inside `range`
+    pub fn get_exec_calls(&self, range: Range) -> Vec<ExecCall> {
+        let mut exec_calls = ExecCalls::new(&self.parser);
+        for e in self.parser.get_elements() {
+            e.accept(&mut exec_calls);
+        }
+        exec_calls
+            .calls
+            .into_iter()
+            .filter(|call| {
+                call.args
+                    .iter()
+                    .any(|(position, _)| range.start <= *position && *position <= range.end)
+            })
+            .collect()
+    }
+
+    /// gets links to the files used by `call`, `readtable` and `readmytable`
+    pub fn get_document_links(&self) -> Vec<DocumentLink> {
+        get_document_links(&self.parser)
+    }
+
     pub fn get_completion(&self, position: Option<Position>) -> Vec<CompletionItem> {
         let mut items = Vec::new();
-        for label in self.parser.labels.keys() {
+        for label in self.parser.get_label_names() {
             items.push(CompletionItem {
                 label: String::from_utf8(label.clone())
                     .unwrap_or_else(|_| UTF8_PARSER_MSG.to_string()),
@@ -234,10 +707,12 @@ pub fn sanitize_string_for_md(s: String) -> String {
 mod tests {
 
     use super::*;
+    use crate::inlay_hints::get_parameter_hints;
+    use tower_lsp::lsp_types::{FoldingRangeKind, InlayHintLabel, ParameterLabel, SymbolKind};
 
     #[test]
     fn test_simple() {
-        let doc = Document::new(
+        let mut doc = Document::new(
             None,
             b"option, echo;\ntwiss, sequence=lhcb1, file=\"twiss.dat\";",
         );
@@ -251,7 +726,7 @@ mod tests {
 
     #[test]
     fn test_incomplete_env() {
-        let doc = Document::new(
+        let mut doc = Document::new(
             None,
             b"option, echo;\nseqedit; flatten;\ntwiss, sequence = lhcb1;",
         );
@@ -265,7 +740,7 @@ mod tests {
 
     #[test]
     fn test_incomplete_generic() {
-        let doc = Document::new(None, b"option, echo;\ncall, fi");
+        let mut doc = Document::new(None, b"option, echo;\ncall, fi");
 
         let st = doc.get_semantic_tokens();
         let completion = doc.get_completion(Some(Position {
@@ -315,6 +790,169 @@ mod tests {
         let _doc = Document::new(None, include_bytes!("../tests/macros/lhc.macros.run3.madx"));
     }
 
+    #[test]
+    fn get_definitions() {
+        let doc = Document::new(
+            None,
+            b"
+do_twiss(a,b): macro = { twiss, sequence=lhcb1;};
+qf: quadrupole, l=3.1;
+option, echo;
+
+exec, DO_TWISS(0, 0);
+                                ",
+        );
+        let uri = Url::from_file_path("/home/job.madx").unwrap();
+
+        let labels = doc.get_labels_under_cursor(Position::new(5, 9));
+        let locations = doc.get_definitions(&labels, &uri);
+
+        assert_eq!(
+            locations,
+            vec![Location::new(
+                uri.clone(),
+                Range::new(Position::new(1, 0), Position::new(1, 8))
+            )]
+        );
+        assert_eq!(
+            doc.get_definitions(&[Cow::Borrowed(b"qf".as_slice())], &uri),
+            vec![Location::new(
+                uri,
+                Range::new(Position::new(2, 0), Position::new(2, 2))
+            )]
+        );
+    }
+
+    #[test]
+    fn get_references() {
+        let doc = Document::new(
+            None,
+            b"
+kqf = 0.01;
+do_twiss(a,b): macro = { twiss, sequence=lhcb1; };
+qf: quadrupole, l=3.1, k1:=KQF;
+exec, do_twiss(KQF, 0);
+if (x > 0) { kqf = kqf * 2; }
+",
+        );
+        let uri = Url::from_file_path("/home/job.madx").unwrap();
+
+        assert_eq!(
+            doc.get_symbol_under_cursor(Position::new(3, 27)),
+            Some(b"kqf".to_vec())
+        );
+        assert_eq!(
+            doc.get_symbol_under_cursor(Position::new(4, 2)),
+            Some(b"exec".to_vec())
+        );
+        assert_eq!(doc.get_symbol_under_cursor(Position::new(0, 0)), None);
+
+        let lines = |locations: Vec<Location>| {
+            locations
+                .iter()
+                .map(|l| (l.range.start.line, l.range.start.character))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines(doc.get_references(b"kqf", false, &uri)),
+            vec![(3, 27), (4, 15), (5, 19)]
+        );
+        assert_eq!(
+            lines(doc.get_references(b"kqf", true, &uri)),
+            vec![(1, 0), (3, 27), (4, 15), (5, 13), (5, 19)]
+        );
+        assert_eq!(
+            lines(doc.get_references(b"do_twiss", false, &uri)),
+            vec![(4, 6)]
+        );
+    }
+
+    #[test]
+    fn get_document_symbols() {
+        let doc = Document::new(
+            None,
+            b"
+kqf := 0.01;
+do_twiss(a,b): macro = {
+  if (a > 0) { qf: quadrupole, l=3.1; }
+};
+match, sequence=lhcb1;
+vary, name=kqf;
+endmatch;
+",
+        );
+        let symbols = doc.get_document_symbols();
+        let summary = |s: &DocumentSymbol| (s.name.clone(), s.kind, s.range.start.line);
+
+        assert_eq!(
+            symbols.iter().map(summary).collect::<Vec<_>>(),
+            vec![
+                ("kqf".to_string(), SymbolKind::VARIABLE, 1),
+                ("do_twiss".to_string(), SymbolKind::FUNCTION, 2),
+                ("match".to_string(), SymbolKind::NAMESPACE, 5),
+            ]
+        );
+
+        let if_block = &symbols[1].children.as_ref().unwrap()[0];
+        assert_eq!(if_block.name, "if (a > 0)");
+        assert_eq!(
+            if_block
+                .children
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(summary)
+                .collect::<Vec<_>>(),
+            vec![("qf".to_string(), SymbolKind::OBJECT, 3)]
+        );
+        assert_eq!(
+            symbols[2]
+                .children
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(summary)
+                .collect::<Vec<_>>(),
+            vec![("vary".to_string(), SymbolKind::METHOD, 6)]
+        );
+    }
+
+    #[test]
+    fn get_workspace_symbols() {
+        let doc = Document::new(
+            None,
+            b"
+define_nominal_beams(): macro = { nominal_energy = 6800; };
+match, sequence=lhcb1;
+vary, name=kqf;
+endmatch;
+if (nominal_energy > 0) { kqf_ir1: quadrupole, l=1; }
+",
+        );
+        let uri = Url::from_file_path("/home/lhc.macros.madx").unwrap();
+        let names = |query| {
+            doc.get_workspace_symbols(query, &uri)
+                .into_iter()
+                .map(|(score, s)| (score, s.name, s.container_name))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names("nominal"),
+            vec![
+                (7, "define_nominal_beams".to_string(), None),
+                (
+                    0,
+                    "nominal_energy".to_string(),
+                    Some("define_nominal_beams".to_string())
+                ),
+            ]
+        );
+        assert!(names("vary").is_empty());
+        // only definitions count, not the parameters and conditions referring to them
+        assert_eq!(names("kqf"), vec![(0, "kqf_ir1".to_string(), None)]);
+    }
+
     #[test]
     fn get_hover() {
         let doc = Document::new(
@@ -329,7 +967,7 @@ exec, do_twiss(0, 0);
 
         let labels = doc.get_labels_under_cursor(Position::new(4, 9));
 
-        assert_eq!(labels, [b"do_twiss"]);
+        assert_eq!(labels, [b"do_twiss".as_slice()]);
         let mut items = vec![];
         let uri = Url::from_file_path("/home").unwrap();
         doc.get_hover(&labels, &mut items, Some(&uri));
@@ -347,4 +985,532 @@ exec, do_twiss(0, 0);
             items
         );
     }
+
+    #[test]
+    fn get_folding_ranges() {
+        let doc = Document::new(
+            None,
+            b"! ---- Optics ----
+! load the optics
+! and the sequence
+do_twiss(a): macro = {
+    twiss, sequence=lhcb1;
+};
+if (a > 0) {
+    exec, do_twiss(a);
+}
+/* a
+ * multiline comment */
+! ---- Matching ----
+match, sequence=lhcb1;
+vary, name=kqf;
+endmatch;
+",
+        );
+
+        let mut ranges = doc
+            .get_folding_ranges()
+            .into_iter()
+            .map(|r| (r.start_line, r.end_line, r.kind))
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|r| (r.0, r.1));
+
+        assert_eq!(
+            ranges,
+            vec![
+                (0, 2, Some(FoldingRangeKind::Comment)),
+                (0, 10, Some(FoldingRangeKind::Region)),
+                (3, 5, None),
+                (6, 8, None),
+                (9, 10, Some(FoldingRangeKind::Comment)),
+                (11, 15, Some(FoldingRangeKind::Region)),
+                (12, 14, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_document_links() {
+        let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let doc = Document::new(
+            Some(Url::from_file_path(tests.join("job.madx")).unwrap()),
+            b"call, file=\"macros/lhc.macros.run3.madx\";
+call, file=\"macros/missing.madx\";
+readtable, file=\"macros/general.macros.madx\";
+",
+        );
+
+        let links = doc
+            .get_document_links()
+            .into_iter()
+            .map(|l| (l.range, l.target))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            links,
+            vec![
+                (
+                    Range::new(Position::new(0, 12), Position::new(0, 39)),
+                    Url::from_file_path(tests.join("macros/lhc.macros.run3.madx")).ok()
+                ),
+                (
+                    Range::new(Position::new(2, 17), Position::new(2, 43)),
+                    Url::from_file_path(tests.join("macros/general.macros.madx")).ok()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_signature_help() {
+        let doc = Document::new(
+            None,
+            b"do_twiss(beam, file): macro = { twiss, sequence=lhcb1, file=file;};
+q: twiss, sequence=lhcb1, -chrom, fi;
+exec, do_twiss(1, 2);
+exec, do_twiss(1, ",
+        );
+
+        let context = doc.get_call_context(Position::new(3, 18));
+        assert_eq!(
+            context,
+            Some(CallContext::Macro {
+                name: b"do_twiss".to_vec(),
+                active: 1
+            })
+        );
+        let signature = doc.get_macro_signature(b"do_twiss", 1).unwrap();
+        assert_eq!(signature.label, "do_twiss(beam, file)");
+        assert_eq!(
+            signature.parameters.unwrap()[1].label,
+            ParameterLabel::LabelOffsets([15, 19])
+        );
+        assert_eq!(signature.active_parameter, Some(1));
+
+        assert_eq!(
+            doc.get_call_context(Position::new(1, 36)),
+            Some(CallContext::Builtin {
+                name: b"twiss".to_vec(),
+                attribute: Some(b"fi".to_vec())
+            })
+        );
+        assert_eq!(
+            doc.get_call_context(Position::new(1, 32)),
+            Some(CallContext::Builtin {
+                name: b"twiss".to_vec(),
+                attribute: Some(b"chrom".to_vec())
+            })
+        );
+        let signature = GENERIC_BUILTINS[b"twiss".as_slice()].get_signature(Some(b"chrom"));
+        assert!(signature
+            .label
+            .starts_with("twiss, sequence, line, range, deltap, chrom, "));
+        assert_eq!(signature.active_parameter, Some(4));
+
+        // the call is already closed
+        assert_eq!(doc.get_call_context(Position::new(2, 20)), None);
+    }
+
+    #[test]
+    fn get_inlay_hints() {
+        let doc = Document::new(
+            None,
+            b"do_twiss(beam, file, i, j): macro = { twiss, sequence=lhcb1, file=file;};
+if (a > 0) {
+    exec, do_twiss(1, \"twiss,b1.dat\", -1.5e-3, f(x, y));
+}
+exec, do_twiss(2, file);
+",
+        );
+
+        let calls = doc.get_exec_calls(Range::new(Position::new(0, 0), Position::new(5, 0)));
+        assert_eq!(
+            calls,
+            vec![
+                ExecCall {
+                    callee: b"do_twiss".to_vec(),
+                    callee_range: Range::new(Position::new(2, 10), Position::new(2, 18)),
+                    args: vec![
+                        (Position::new(2, 19), "1".to_string()),
+                        (Position::new(2, 22), "\"twiss,b1.dat\"".to_string()),
+                        (Position::new(2, 38), "-1.5e-3".to_string()),
+                        (Position::new(2, 47), "f(x, y)".to_string()),
+                    ]
+                },
+                ExecCall {
+                    callee: b"do_twiss".to_vec(),
+                    callee_range: Range::new(Position::new(4, 6), Position::new(4, 14)),
+                    args: vec![
+                        (Position::new(4, 15), "2".to_string()),
+                        (Position::new(4, 18), "file".to_string()),
+                    ]
+                },
+            ]
+        );
+
+        let args = doc.get_macro_args(b"do_twiss").unwrap();
+        let hints = get_parameter_hints(&calls[1], &args);
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].position, Position::new(4, 15));
+        assert!(matches!(&hints[0].label, InlayHintLabel::String(label) if label == "beam:"));
+    }
+
+    #[test]
+    fn get_top_level_definitions() {
+        let doc = Document::new(
+            None,
+            b"do_twiss(a): macro = { twiss, sequence=lhcb1;};
+unused(a): macro = { exec, do_twiss(a); };
+qf: quadrupole, l=3.1;
+kqf = 0.01;
+exec, do_twiss(kqf);
+",
+        );
+
+        let definitions = doc
+            .get_top_level_definitions()
+            .into_iter()
+            .map(|(name, range)| {
+                let count = doc.count_references(&name);
+                (name, range.start.line, count)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            definitions,
+            vec![
+                (b"do_twiss".to_vec(), 0, 2),
+                (b"unused".to_vec(), 1, 0),
+                (b"qf".to_vec(), 2, 0),
+                (b"kqf".to_vec(), 3, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_call_hierarchy() {
+        let doc = Document::new(
+            None,
+            b"set_crossing(on): macro = { xing = on; };
+define_beams(): macro = {
+    beam, particle=proton;
+    if (xing > 0) {
+        exec, set_crossing(1);
+    }
+    exec, set_crossing(0);
+};
+exec, define_beams();
+exec, set_crossing(1);
+",
+        );
+        let uri = Url::from_file_path("/home/job.madx").unwrap();
+
+        let item = doc.get_call_hierarchy_item(b"define_beams", &uri).unwrap();
+        assert_eq!(item.name, "define_beams");
+        assert_eq!(item.selection_range.start, Position::new(1, 0));
+
+        let outgoing = doc.get_outgoing_calls(b"define_beams");
+        assert_eq!(
+            outgoing,
+            vec![(
+                b"set_crossing".to_vec(),
+                vec![
+                    Range::new(Position::new(4, 14), Position::new(4, 26)),
+                    Range::new(Position::new(6, 10), Position::new(6, 22)),
+                ]
+            )]
+        );
+
+        let incoming = doc
+            .get_incoming_calls(b"set_crossing", &uri)
+            .into_iter()
+            .map(|call| (call.from.name, call.from.kind, call.from_ranges.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            incoming,
+            vec![
+                ("define_beams".to_string(), SymbolKind::FUNCTION, 2),
+                ("job.madx".to_string(), SymbolKind::FILE, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_type_hierarchy() {
+        let doc = Document::new(
+            None,
+            b"mq: quadrupole, l=3.1;
+mq.a1: mq, k1:=kq1;
+mq.a2: mq, k1:=kq2, tilt=0.1;
+",
+        );
+        let uri = Url::from_file_path("/home/lhc.seq").unwrap();
+
+        assert_eq!(
+            doc.get_element_definition(b"mq.a2"),
+            Some((
+                b"mq".to_vec(),
+                Range::new(Position::new(2, 7), Position::new(2, 9)),
+                vec!["k1:=kq2".to_string(), "tilt=0.1".to_string()]
+            ))
+        );
+        assert_eq!(
+            doc.get_element_definition(b"mq").map(|(class, _, _)| class),
+            Some(b"quadrupole".to_vec())
+        );
+
+        let item = doc.get_type_hierarchy_item(b"mq", &uri).unwrap();
+        assert_eq!(item.detail, Some("quadrupole".to_string()));
+        assert_eq!(
+            doc.get_subtypes(b"mq", &uri)
+                .into_iter()
+                .map(|item| item.name)
+                .collect::<Vec<_>>(),
+            vec!["mq.a1", "mq.a2"]
+        );
+        assert_eq!(doc.get_subtypes(b"quadrupole", &uri).len(), 1);
+    }
+
+    #[test]
+    fn get_selection_ranges() {
+        let doc = Document::new(
+            None,
+            b"do_twiss(a): macro = {
+    select, flag=twiss;
+    twiss, sequence=lhcb1, file=a;
+};
+",
+        );
+
+        let mut selection = doc
+            .get_selection_ranges(&[Position::new(2, 23)])
+            .pop()
+            .map(Box::new);
+        let mut ranges = Vec::new();
+        while let Some(s) = selection {
+            ranges.push(doc.parser.get_element_str(&(
+                doc.parser.lexer.cursor_pos_from_text_pos(s.range.start),
+                doc.parser.lexer.cursor_pos_from_text_pos(s.range.end),
+            )));
+            selection = s.parent;
+        }
+
+        assert_eq!(
+            ranges,
+            vec![
+                "lhcb1",
+                "sequence=lhcb1",
+                "twiss, sequence=lhcb1, file=a",
+                "select, flag=twiss;\n    twiss, sequence=lhcb1, file=a;",
+                "do_twiss(a): macro = {\n    select, flag=twiss;\n    twiss, sequence=lhcb1, file=a;\n}",
+            ]
+        );
+    }
+
+    #[test]
+    fn get_selection_ranges_in_if() {
+        let doc = Document::new(
+            None,
+            b"do_twiss(a): macro = {
+    if (a > 0) {
+        select, flag=twiss;
+        twiss, sequence=lhcb1, file=a;
+    }
+};
+",
+        );
+
+        let mut selection = doc
+            .get_selection_ranges(&[Position::new(3, 27)])
+            .pop()
+            .map(Box::new);
+        let mut ranges = Vec::new();
+        while let Some(s) = selection {
+            ranges.push(doc.parser.get_element_str(&(
+                doc.parser.lexer.cursor_pos_from_text_pos(s.range.start),
+                doc.parser.lexer.cursor_pos_from_text_pos(s.range.end),
+            )));
+            selection = s.parent;
+        }
+
+        assert_eq!(
+            ranges,
+            vec![
+                "lhcb1",
+                "sequence=lhcb1",
+                "twiss, sequence=lhcb1, file=a",
+                "select, flag=twiss;\n        twiss, sequence=lhcb1, file=a;",
+                "if (a > 0) {\n        select, flag=twiss;\n        twiss, sequence=lhcb1, file=a;\n    }",
+                "do_twiss(a): macro = {\n    if (a > 0) {\n        select, flag=twiss;\n        twiss, sequence=lhcb1, file=a;\n    }\n}",
+            ]
+        );
+    }
+
+    #[test]
+    fn diagnostics_and_revision() {
+        let mut doc = Document::new(
+            None,
+            b"select, flag=twiss, column=name,s,betx;\nvalue, table(twiss, ip1, betx);",
+        );
+        assert!(doc.get_diagnostics().is_empty());
+
+        let revision = doc.revision();
+        doc.reload(b"twiss;");
+        assert_ne!(doc.revision(), revision);
+        assert!(doc.get_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn incremental_edits() {
+        let mut doc = Document::new(None, "! 😀 x\nb = 2;\r\nc = 3;".as_bytes());
+        let revision = doc.revision();
+
+        // the emoji counts two utf-16 code units
+        doc.edit(Range::new(Position::new(0, 5), Position::new(0, 6)), "y");
+        doc.edit(Range::new(Position::new(1, 4), Position::new(1, 5)), "42");
+        // behind the end of the line
+        doc.edit(
+            Range::new(Position::new(2, 6), Position::new(2, 99)),
+            "\nd = 4;",
+        );
+        assert_ne!(doc.revision(), revision);
+
+        let text = "! 😀 y\nb = 42;\r\nc = 3;\nd = 4;";
+        assert_eq!(doc.parser.lexer.get_text().to_string(), text);
+        let expected = Document::new(None, text.as_bytes());
+        doc.parser.close_gap();
+        assert_eq!(
+            doc.parser.lexer.get_tokens(),
+            expected.parser.lexer.get_tokens()
+        );
+        assert_eq!(doc.parser.get_elements(), expected.parser.get_elements());
+    }
+
+    #[test]
+    fn semantic_tokens_delta_and_range() {
+        let mut doc = Document::new(
+            None,
+            b"option, echo;\nselect, flag=twiss;\ntwiss, file=\"a\";",
+        );
+        let Ok(Some(SemanticTokensResult::Tokens(sent))) = doc.get_semantic_tokens() else {
+            panic!("no semantic tokens");
+        };
+
+        doc.edit(
+            Range::new(Position::new(1, 0), Position::new(1, 0)),
+            "! comment\n",
+        );
+        let Ok(Some(SemanticTokensFullDeltaResult::TokensDelta(delta))) =
+            doc.get_semantic_tokens_delta(sent.result_id.as_deref().unwrap())
+        else {
+            panic!("no semantic tokens delta");
+        };
+        assert_eq!(delta.edits.len(), 1);
+        let mut data = sent.data.clone();
+        for edit in delta.edits {
+            let start = edit.start as usize / 5;
+            let end = start + edit.delete_count as usize / 5;
+            data.splice(start..end, edit.data.unwrap());
+        }
+        let expected = Document::new(
+            None,
+            b"option, echo;\n! comment\nselect, flag=twiss;\ntwiss, file=\"a\";",
+        );
+        let expected = expected.get_semantic_token_data(expected.parser.get_elements().iter());
+        assert_eq!(data, expected);
+        assert!(data.len() > 2);
+
+        // tokens sent with an unknown result id are replaced as a whole
+        assert!(matches!(
+            doc.get_semantic_tokens_delta(&sent.result_id.unwrap()),
+            Ok(Some(SemanticTokensFullDeltaResult::Tokens(_)))
+        ));
+
+        let Ok(Some(SemanticTokensRangeResult::Tokens(range))) =
+            doc.get_semantic_tokens_range(Range::new(Position::new(2, 0), Position::new(99, 0)))
+        else {
+            panic!("no semantic tokens in range");
+        };
+        assert_eq!(range.data[0].delta_line, 2);
+        assert!(range.data.len() < data.len());
+        assert_eq!(range.data[1..], data[data.len() - range.data.len() + 1..]);
+    }
+
+    #[test]
+    fn semantic_tokens_range_of_unclosed_exec() {
+        let doc = Document::new(None, b"a = 1;\nexec, m(a;\nb = 2;");
+        let ranges: Vec<_> = doc
+            .parser
+            .get_elements()
+            .iter()
+            .map(|e| e.get_range())
+            .collect();
+        assert!(ranges.iter().all(|r| r.0 < r.1), "{:?}", ranges);
+        assert!(ranges.windows(2).all(|w| w[0].1 <= w[1].0), "{:?}", ranges);
+
+        let Ok(Some(SemanticTokensRangeResult::Tokens(range))) =
+            doc.get_semantic_tokens_range(Range::new(Position::new(1, 0), Position::new(1, 10)))
+        else {
+            panic!("no semantic tokens in range");
+        };
+        let types: Vec<_> = range.data.iter().map(|t| t.token_type).collect();
+        assert_eq!(range.data[0].delta_line, 1);
+        assert_eq!(types, [8, 4, 9]);
+    }
+
+    #[test]
+    fn document_highlights() {
+        let doc = Document::new(
+            None,
+            b"a = 1;\nif (a > 0) {\n  a := a + 1;\n}\nm(x): macro = {\n  b = a;\n  x = x + 1;\n}",
+        );
+        let highlights = |line, character| {
+            doc.get_document_highlights(&Position::new(line, character))
+                .unwrap()
+                .unwrap()
+                .iter()
+                .map(|h| {
+                    let write = h.kind == Some(DocumentHighlightKind::WRITE);
+                    (h.range.start.line, h.range.start.character, write)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let a = [
+            (0, 0, true),
+            (1, 4, false),
+            (2, 2, true),
+            (2, 7, false),
+            (5, 6, false),
+        ];
+        assert_eq!(highlights(0, 0), a);
+        assert_eq!(highlights(5, 6), a);
+        assert_eq!(
+            highlights(6, 6),
+            [(4, 2, true), (6, 2, true), (6, 6, false)]
+        );
+    }
+
+    #[test]
+    fn macro_arguments_shadow_symbols() {
+        let doc = Document::new(
+            None,
+            b"x = 1;\nm(x): macro = {\n  y = x;\n  x = 2;\n}\nz = x;",
+        );
+        let highlights = doc
+            .get_document_highlights(&Position::new(0, 0))
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(|h| (h.range.start.line, h.range.start.character))
+            .collect::<Vec<_>>();
+        assert_eq!(highlights, [(0, 0), (5, 4)]);
+
+        let uri = Url::parse("file:///a.madx").unwrap();
+        let references = doc
+            .get_references(b"x", true, &uri)
+            .iter()
+            .map(|l| (l.range.start.line, l.range.start.character))
+            .collect::<Vec<_>>();
+        assert_eq!(references, [(0, 0), (5, 4)]);
+    }
 }
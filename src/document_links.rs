@@ -0,0 +1,77 @@
+use tower_lsp::lsp_types::{DocumentLink, Url};
+
+use crate::{
+    lexer::CursorPosition,
+    parser::{get_path_relative_to_parent, Expression, Label, Macro, MadExec, MadGeneric, Parser},
+    visitor::Visitor,
+};
+
+/// commands whose `file` argument points to another file
+const FILE_COMMANDS: [&[u8]; 3] = [b"call", b"readtable", b"readmytable"];
+
+/// collects the ranges of the (unquoted) `file` arguments of [`FILE_COMMANDS`]
+pub struct FileArgs<'a> {
+    parser: &'a Parser,
+    pub ranges: Vec<(CursorPosition, CursorPosition)>,
+}
+
+impl<'a> FileArgs<'a> {
+    pub fn new(parser: &'a Parser) -> Self {
+        Self {
+            parser,
+            ranges: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Visitor for FileArgs<'a> {
+    fn visit_macro(&mut self, _macro_exp: &Macro) {}
+
+    fn visit_exec(&mut self, _exec_exp: &MadExec) {}
+
+    fn visit_label(&mut self, _label: &Label) {}
+
+    fn visit_if(&mut self, _if_exp: &crate::parser::If) {}
+
+    fn visit_while(&mut self, _while_exp: &crate::parser::While) {}
+
+    fn visit_generic(&mut self, generic: &MadGeneric) {
+        if !FILE_COMMANDS.contains(&generic.match_name) {
+            return;
+        }
+        for arg in generic.args.iter() {
+            if !self.parser.lexer.compare_range(&arg.attribute, b"file") {
+                continue;
+            }
+            if let Some(Expression::String(range)) = arg.value.as_deref() {
+                self.ranges
+                    .push(self.parser.lexer.get_string_contents(range));
+            }
+        }
+    }
+}
+
+/// turns the file arguments of the document into links, paths that can't be resolved are skipped
+pub fn get_document_links(parser: &Parser) -> Vec<DocumentLink> {
+    let mut file_args = FileArgs::new(parser);
+    for e in parser.get_elements() {
+        e.accept(&mut file_args);
+    }
+
+    file_args
+        .ranges
+        .iter()
+        .filter_map(|range| {
+            let path = get_path_relative_to_parent(
+                parser.uri(),
+                parser.get_element_bytes(range).to_vec(),
+            )?;
+            Some(DocumentLink {
+                range: parser.lexer.range_to_text_range(range),
+                target: Some(Url::from_file_path(path).ok()?),
+                tooltip: None,
+                data: None,
+            })
+        })
+        .collect()
+}
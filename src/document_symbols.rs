@@ -0,0 +1,108 @@
+use tower_lsp::lsp_types::{DocumentSymbol, Location, SymbolInformation, SymbolKind, Url};
+
+use crate::{
+    lexer::{CursorPosition, HasRange},
+    parser::{Expression, Parser},
+};
+
+#[allow(deprecated)] // `DocumentSymbol::deprecated` has to be set, even though it's deprecated
+pub fn get_document_symbol<R: HasRange, S: HasRange>(
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    range: &R,
+    selection_range: &S,
+    children: Vec<DocumentSymbol>,
+    parser: &Parser,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        name,
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: parser.lexer.range_to_text_range(range),
+        selection_range: parser.lexer.range_to_text_range(selection_range),
+        children: if children.is_empty() {
+            None
+        } else {
+            Some(children)
+        },
+    }
+}
+
+/// turns the `definitions` (see `Expression::get_definitions`) whose name matches `query` into
+/// workspace symbols. The symbols are returned together with their `fuzzy_match` score.
+#[allow(deprecated)] // `SymbolInformation::deprecated` has to be set, even though it's deprecated
+pub fn get_workspace_symbols(
+    definitions: &[(&Expression, Option<(CursorPosition, CursorPosition)>)],
+    parser: &Parser,
+    query: &str,
+    uri: &Url,
+    matches: &mut Vec<(usize, SymbolInformation)>,
+) {
+    for (definition, container) in definitions.iter() {
+        let (Some(range), Some(kind)) = (definition.get_name_range(), get_symbol_kind(definition))
+        else {
+            continue;
+        };
+        let name = parser.get_element_str(&range);
+        if let Some(score) = fuzzy_match(query, &name) {
+            matches.push((
+                score,
+                SymbolInformation {
+                    name,
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location::new(
+                        uri.clone(),
+                        parser.lexer.range_to_text_range(&range),
+                    ),
+                    container_name: container.map(|c| parser.get_element_str(&c)),
+                },
+            ));
+        }
+    }
+}
+
+/// the kind of a definition, the same as in the document outline
+fn get_symbol_kind(definition: &Expression) -> Option<SymbolKind> {
+    match definition {
+        Expression::Label(_) => Some(SymbolKind::OBJECT),
+        Expression::Macro(_) => Some(SymbolKind::FUNCTION),
+        Expression::Assignment(_) => Some(SymbolKind::VARIABLE),
+        _ => None,
+    }
+}
+
+/// case insensitive fuzzy matching: `query` has to be a subsequence of `name`.
+/// Returns the number of characters skipped in `name` until the end of the match (lower is
+/// better, 0 is a prefix match), or `None` if `name` doesn't match.
+pub fn fuzzy_match(query: &str, name: &str) -> Option<usize> {
+    let mut score = 0;
+    let mut name_chars = name.chars().map(|c| c.to_ascii_lowercase());
+    for q in query.chars().map(|c| c.to_ascii_lowercase()) {
+        loop {
+            match name_chars.next() {
+                Some(c) if c == q => break,
+                Some(_) => score += 1,
+                None => return None,
+            }
+        }
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_match("", "define_nominal_beams"), Some(0));
+        assert_eq!(fuzzy_match("define", "define_nominal_beams"), Some(0));
+        assert_eq!(fuzzy_match("DNB", "define_nominal_beams"), Some(13));
+        assert_eq!(fuzzy_match("dnb", "do_twiss"), None);
+    }
+}
@@ -0,0 +1,106 @@
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+
+use crate::{
+    lexer::{CursorPosition, HasRange, Token},
+    parser::Parser,
+};
+
+/// returns a folding range from the line of `start` to the line of `end`, if they differ
+pub fn get_folding_range(
+    start: CursorPosition,
+    end: CursorPosition,
+    kind: Option<FoldingRangeKind>,
+    parser: &Parser,
+) -> Option<FoldingRange> {
+    let (start, end) = (parser.lexer.line(&start), parser.lexer.line(&end));
+    if end <= start {
+        return None;
+    }
+    Some(FoldingRange {
+        start_line: start as u32,
+        end_line: end as u32,
+        kind,
+        ..Default::default()
+    })
+}
+
+/// collects the folding ranges of comments:
+/// - multiline comments
+/// - runs of consecutive comment lines (`!` and `//` comments that are alone on their line)
+/// - banner comments like `! ----- Section -----`, they fold as regions until the next banner
+pub fn get_comment_folding_ranges(parser: &Parser, ranges: &mut Vec<FoldingRange>) {
+    let mut last_line = None;
+    let mut run: Option<(CursorPosition, CursorPosition)> = None;
+    let mut banners = Vec::new();
+
+    for token in parser.lexer.get_tokens() {
+        match token {
+            Token::MultilineComment(_) => {
+                let range = token.get_range();
+                ranges.extend(get_folding_range(
+                    range.0,
+                    range.1,
+                    Some(FoldingRangeKind::Comment),
+                    parser,
+                ));
+            }
+            Token::Comment(range) if last_line != Some(parser.lexer.line(&range.0)) => {
+                if is_banner(&parser.get_element_bytes(range)) {
+                    banners.push(parser.lexer.line(&range.0));
+                }
+                run = match run {
+                    Some((start, end))
+                        if parser.lexer.line(&end) + 1 == parser.lexer.line(&range.0) =>
+                    {
+                        Some((start, range.1))
+                    }
+                    _ => {
+                        if let Some((start, end)) = run {
+                            ranges.extend(get_folding_range(
+                                start,
+                                end,
+                                Some(FoldingRangeKind::Comment),
+                                parser,
+                            ));
+                        }
+                        Some(*range)
+                    }
+                };
+            }
+            _ => {}
+        }
+        last_line = Some(parser.lexer.line(&token.get_range().1));
+    }
+    if let Some((start, end)) = run {
+        ranges.extend(get_folding_range(
+            start,
+            end,
+            Some(FoldingRangeKind::Comment),
+            parser,
+        ));
+    }
+
+    let last_line = parser.lexer.line_count().saturating_sub(1);
+    for (i, banner) in banners.iter().enumerate() {
+        let end_line = banners.get(i + 1).map_or(last_line, |next| next - 1);
+        if end_line > *banner {
+            ranges.push(FoldingRange {
+                start_line: *banner as u32,
+                end_line: end_line as u32,
+                kind: Some(FoldingRangeKind::Region),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+/// a banner is a comment whose text starts with at least three `-` or `=`
+fn is_banner(comment: &[u8]) -> bool {
+    let text = comment
+        .iter()
+        .skip_while(|c| matches!(c, b'!' | b'/'))
+        .skip_while(|c| c.is_ascii_whitespace())
+        .copied()
+        .collect::<Vec<u8>>();
+    text.starts_with(b"---") || text.starts_with(b"===")
+}
@@ -0,0 +1,83 @@
+use tower_lsp::lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, Position, Range};
+
+use crate::{
+    parser::{If, Label, Macro, MadExec, MadGeneric, Parser, While},
+    visitor::Visitor,
+};
+
+/// an `exec, name(args)` call
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExecCall {
+    /// the lowercase name of the called macro
+    pub callee: Vec<u8>,
+    pub callee_range: Range,
+    /// start position and text of each argument
+    pub args: Vec<(Position, String)>,
+}
+
+/// collects the exec calls of the visited expressions, including the ones inside macros and
+/// if-blocks
+pub struct ExecCalls<'a> {
+    parser: &'a Parser,
+    pub calls: Vec<ExecCall>,
+}
+
+impl<'a> ExecCalls<'a> {
+    pub fn new(parser: &'a Parser) -> Self {
+        Self {
+            parser,
+            calls: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Visitor for ExecCalls<'a> {
+    fn visit_macro(&mut self, _macro_exp: &Macro) {}
+
+    fn visit_exec(&mut self, exec_exp: &MadExec) {
+        let args = exec_exp
+            .get_arguments()
+            .iter()
+            .map(|arg| {
+                (
+                    self.parser.lexer.cursor_pos_to_text_pos(arg.0),
+                    self.parser.get_element_str(arg),
+                )
+            })
+            .collect();
+        let callee = exec_exp.get_callee();
+        self.calls.push(ExecCall {
+            callee: self.parser.get_element_bytes(&callee).to_ascii_lowercase(),
+            callee_range: self.parser.lexer.range_to_text_range(&callee),
+            args,
+        });
+    }
+
+    fn visit_label(&mut self, _label: &Label) {}
+
+    fn visit_if(&mut self, _if_exp: &If) {}
+
+    fn visit_while(&mut self, _while_exp: &While) {}
+
+    fn visit_generic(&mut self, _generic: &MadGeneric) {}
+}
+
+/// annotates the arguments of `call` with the names of the macro parameters `params`.
+/// Arguments that are spelled like their parameter don't need a hint.
+pub fn get_parameter_hints(call: &ExecCall, params: &[String]) -> Vec<InlayHint> {
+    call.args
+        .iter()
+        .zip(params)
+        .filter(|((_, arg), param)| !arg.eq_ignore_ascii_case(param))
+        .map(|((position, _), param)| InlayHint {
+            position: *position,
+            label: InlayHintLabel::String(format!("{}:", param)),
+            kind: Some(InlayHintKind::PARAMETER),
+            text_edits: None,
+            tooltip: None,
+            padding_left: None,
+            padding_right: Some(true),
+            data: None,
+        })
+        .collect()
+}
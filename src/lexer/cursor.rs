@@ -1,27 +1,35 @@
 use std::{ops::{AddAssign, Add, SubAssign}, fmt::Display};
 
 
+/// a position in the buffer of the `Lexer`, which can translate it to a byte offset and line.
+///
+/// The positions behind the gap (see `Offset`) don't hold their byte offset and line but the ones
+/// they had when they moved behind it, the lexer adds how far the text behind the gap moved since.
+/// That way an edit doesn't have to touch the positions behind it. Positions still compare
+/// without the lexer, the ones behind the gap are greater than the ones in front of it.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct CursorPosition {
-    absolute: usize,
-    line: usize,
+    absolute: isize,
+    line: i32,
+    behind_gap: bool,
 }
 
 impl Display for CursorPosition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.absolute, self.line)
+        let gap = if self.behind_gap { "+" } else { "" };
+        write!(f, "{}{}:{}", gap, self.absolute, self.line)
     }
 }
 
 impl AddAssign<usize> for CursorPosition {
     fn add_assign(&mut self, rhs: usize) {
-        self.absolute += rhs;
+        self.absolute += rhs as isize;
     }
 }
 
 impl SubAssign<usize> for CursorPosition {
     fn sub_assign(&mut self, rhs: usize) {
-        self.absolute -= rhs;
+        self.absolute -= rhs as isize;
     }
 }
 
@@ -30,8 +38,8 @@ impl Add<usize> for CursorPosition {
 
     fn add(self, rhs: usize) -> Self::Output {
         Self {
-            absolute: self.absolute + rhs,
-            line: self.line,
+            absolute: self.absolute + rhs as isize,
+            ..self
         }
     }
 }
@@ -40,10 +48,7 @@ impl Add<usize> for &CursorPosition {
     type Output = CursorPosition;
 
     fn add(self, rhs: usize) -> Self::Output {
-        Self::Output {
-            absolute: self.absolute + rhs,
-            line: self.line,
-        }
+        *self + rhs
     }
 }
 
@@ -55,15 +60,17 @@ impl PartialOrd for CursorPosition {
 
 impl Ord for CursorPosition {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.absolute.cmp(&other.absolute)
+        (self.behind_gap, self.absolute).cmp(&(other.behind_gap, other.absolute))
     }
 }
 
 impl CursorPosition {
-    pub fn new(absolute: usize, line: usize) -> Self {
+    /// the position at byte `absolute` on `line`, in front of the gap
+    pub(super) fn new(absolute: usize, line: usize) -> Self {
         Self {
-            absolute,
-            line
+            absolute: absolute as isize,
+            line: line as i32,
+            behind_gap: false,
         }
     }
 
@@ -71,27 +78,66 @@ impl CursorPosition {
         self.line += 1;
     }
 
-    pub fn character(&self, lines: &[usize]) -> usize {
-        self.absolute - lines[self.line]
+    /// the byte offset and line, for a position in front of the gap
+    pub(super) fn front(&self) -> Option<(usize, usize)> {
+        (!self.behind_gap).then_some((self.absolute as usize, self.line as usize))
+    }
+
+    /// the byte offset and line, `offset` tells how far the text behind the gap moved
+    pub fn resolve(&self, offset: &Offset) -> (usize, usize) {
+        if self.behind_gap {
+            (
+                (self.absolute + offset.bytes) as usize,
+                (self.line as isize + offset.lines) as usize,
+            )
+        } else {
+            (self.absolute as usize, self.line as usize)
+        }
     }
 
-    pub fn line(&self) -> usize {
-        self.line
+    /// moves the position to the other side of the gap if it lies between `offset.from` and
+    /// `offset.to`, see `Offset`
+    pub fn shift(&mut self, offset: &Offset) {
+        let absolute = self.resolve(offset).0;
+        if absolute < offset.from || absolute >= offset.to {
+            return;
+        }
+        let (bytes, lines) = if self.behind_gap {
+            (offset.bytes, offset.lines)
+        } else {
+            (-offset.bytes, -offset.lines)
+        };
+        self.absolute += bytes;
+        self.line += lines as i32;
+        self.behind_gap = !self.behind_gap;
     }
+}
 
-    pub(crate) fn absolute(&self) -> usize {
-        self.absolute
+/// how the gap moves.
+///
+/// The gap is where the last edit was lexed again, the positions behind it hold where they were
+/// when they moved behind it. `bytes` and `lines` are how far the text behind the gap moved since.
+/// When the gap moves, the positions between `from` and `to` change sides, the ones behind the
+/// gap stay untouched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Offset {
+    pub from: usize,
+    pub to: usize,
+    pub bytes: isize,
+    pub lines: isize,
+}
+
+impl Offset {
+    /// whether the gap moves over the positions of `range`
+    pub fn overlaps(&self, range: &(CursorPosition, CursorPosition)) -> bool {
+        self.from < self.to
+            && range.0.resolve(self).0 < self.to
+            && range.1.resolve(self).0 >= self.from
     }
 }
 
 impl Default for CursorPosition {
     fn default() -> Self {
-        Self{
-            absolute: 0,
-            line: 0
-        }
+        Self::new(0, 0)
     }
 }
-
-
-
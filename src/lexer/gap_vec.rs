@@ -0,0 +1,204 @@
+use std::{
+    fmt::Debug,
+    iter::{Chain, Rev},
+    ops::Index,
+    slice,
+};
+
+/// a vector with a gap, items are inserted and removed at the gap without moving the ones behind
+/// it.
+///
+/// The tokens and elements keep their gap where the document was edited last. Edits mostly
+/// happen close to each other, moving the gap only costs the items in between.
+#[derive(Clone)]
+pub struct GapVec<T> {
+    front: Vec<T>,
+    /// the items behind the gap, in reverse order
+    back: Vec<T>,
+}
+
+pub type Iter<'a, T> = Chain<slice::Iter<'a, T>, Rev<slice::Iter<'a, T>>>;
+
+impl<T> GapVec<T> {
+    pub fn new() -> Self {
+        Self {
+            front: Vec::new(),
+            back: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.front.len() + self.back.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.front.is_empty() && self.back.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match index.checked_sub(self.front.len()) {
+            None => self.front.get(index),
+            Some(behind) => self
+                .back
+                .len()
+                .checked_sub(behind + 1)
+                .map(|i| &self.back[i]),
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match index.checked_sub(self.front.len()) {
+            None => self.front.get_mut(index),
+            Some(behind) => {
+                let i = self.back.len().checked_sub(behind + 1)?;
+                Some(&mut self.back[i])
+            }
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.front.first().or_else(|| self.back.last())
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.back.first().or_else(|| self.front.last())
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.front.iter().chain(self.back.iter().rev())
+    }
+
+    /// the index of the first item for which `pred` is false, see `slice::partition_point`
+    pub fn partition_point<P: FnMut(&T) -> bool>(&self, mut pred: P) -> usize {
+        match self.front.last() {
+            Some(last) if !pred(last) => self.front.partition_point(pred),
+            _ => self.front.len() + self.back.len() - self.back.partition_point(|t| !pred(t)),
+        }
+    }
+
+    /// the index of the first item behind the gap
+    pub fn gap(&self) -> usize {
+        self.front.len()
+    }
+
+    /// moves the gap in front of the item at `index`
+    pub fn move_gap(&mut self, index: usize) {
+        while self.front.len() > index {
+            self.back.extend(self.front.pop());
+        }
+        while self.front.len() < index {
+            match self.back.pop() {
+                Some(item) => self.front.push(item),
+                None => break,
+            }
+        }
+    }
+
+    /// inserts `item` in front of the gap
+    pub fn push(&mut self, item: T) {
+        self.front.push(item);
+    }
+
+    /// the item behind the gap
+    pub fn behind_gap(&self) -> Option<&T> {
+        self.back.last()
+    }
+
+    /// removes the item behind the gap
+    pub fn remove_behind_gap(&mut self) -> Option<T> {
+        self.back.pop()
+    }
+
+    /// the items from the gap on
+    pub fn iter_mut_behind_gap(&mut self) -> Rev<slice::IterMut<'_, T>> {
+        self.back.iter_mut().rev()
+    }
+
+    pub fn clear(&mut self) {
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+impl<T> Default for GapVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Index<usize> for GapVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        match self.get(index) {
+            Some(item) => item,
+            None => panic!("index {} out of range for length {}", index, self.len()),
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a GapVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: PartialEq> PartialEq for GapVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Debug> Debug for GapVec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_at_the_gap() {
+        let mut items = GapVec::new();
+        for i in 0..6 {
+            items.push(i);
+        }
+        items.move_gap(2);
+        assert_eq!(items.remove_behind_gap(), Some(2));
+        items.push(7);
+        items.push(8);
+        assert_eq!(
+            items.iter().copied().collect::<Vec<_>>(),
+            [0, 1, 7, 8, 3, 4, 5]
+        );
+        assert_eq!((items.len(), items.gap()), (7, 4));
+        assert_eq!((items[1], items[4], items.get(7)), (1, 3, None));
+        assert_eq!((items.first(), items.last()), (Some(&0), Some(&5)));
+        assert_eq!(items.behind_gap(), Some(&3));
+
+        // with the gap anywhere
+        for gap in 0..=7 {
+            let mut sorted = GapVec::new();
+            for i in 0..7 {
+                sorted.push(i);
+            }
+            sorted.move_gap(gap);
+            for point in 0..=7 {
+                assert_eq!(sorted.partition_point(|i| *i < point), point as usize);
+            }
+        }
+
+        items.move_gap(items.len());
+        assert_eq!(items.behind_gap(), None);
+        items.move_gap(0);
+        assert_eq!(
+            items.iter().copied().collect::<Vec<_>>(),
+            [0, 1, 7, 8, 3, 4, 5]
+        );
+    }
+}
@@ -1,13 +1,14 @@
 use std::{borrow::Cow, fmt::Display, io, path::Path};
 
 pub mod cursor;
+pub mod gap_vec;
 pub mod token;
 
 pub use cursor::*;
+pub use gap_vec::*;
+use ropey::Rope;
 pub use token::*;
-use tower_lsp::lsp_types::Position;
-
-use crate::error::UTF8_PARSER_MSG;
+use tower_lsp::lsp_types::{Position, Range};
 
 pub trait HasRange {
     fn get_range(&self) -> (CursorPosition, CursorPosition);
@@ -25,12 +26,94 @@ impl HasRange for &(CursorPosition, CursorPosition) {
     }
 }
 
+/// everything that holds positions into the buffer, so they can move to the other side of the gap
+pub trait Shift {
+    fn shift(&mut self, offset: &Offset);
+}
+
+impl Shift for CursorPosition {
+    fn shift(&mut self, offset: &Offset) {
+        CursorPosition::shift(self, offset)
+    }
+}
+
+impl<A: Shift, B: Shift> Shift for (A, B) {
+    fn shift(&mut self, offset: &Offset) {
+        self.0.shift(offset);
+        self.1.shift(offset);
+    }
+}
+
+impl<T: Shift> Shift for [T] {
+    fn shift(&mut self, offset: &Offset) {
+        for item in self.iter_mut() {
+            item.shift(offset);
+        }
+    }
+}
+
+impl<T: Shift> Shift for Vec<T> {
+    fn shift(&mut self, offset: &Offset) {
+        self.as_mut_slice().shift(offset);
+    }
+}
+
+impl<T: Shift> Shift for Option<T> {
+    fn shift(&mut self, offset: &Offset) {
+        if let Some(item) = self {
+            item.shift(offset);
+        }
+    }
+}
+
+impl<T: Shift> Shift for Box<T> {
+    fn shift(&mut self, offset: &Offset) {
+        (**self).shift(offset);
+    }
+}
+
+/// a token variant holding only its start, like `Token::Less`
+type PositionToken = fn(CursorPosition) -> Token;
+
+/// how many bytes the lexer looks ahead of its position at most
+const LOOKAHEAD: usize = 4;
+
+/// the bytes copied at least into the window when an edit is lexed
+const WINDOW: usize = 4096;
+
+/// the tokens replaced by `Lexer::edit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenEdit {
+    /// index of the first re-lexed token
+    pub first: usize,
+    /// number of old tokens that were replaced
+    pub removed: usize,
+    /// number of new tokens
+    pub inserted: usize,
+    /// where the first re-lexed token starts
+    pub start: CursorPosition,
+    /// how the gap moved to `start` before the edit, everything holding positions has to follow
+    pub gap: Offset,
+}
+
 #[derive(Debug)]
 pub struct Lexer {
-    buffer: Vec<u8>,
-    lines: Vec<usize>,
+    buffer: Rope,
+    /// the bytes lexed last, starting at byte `window_start` of the buffer. They stay around for
+    /// `get_bytes`, the parser reads the same bytes right after
+    window: Vec<u8>,
+    window_start: usize,
     position: CursorPosition,
-    tokens: Vec<Token>,
+    /// the tokens in front of `gap` in front of the gap of the vector too
+    tokens: GapVec<Token>,
+    /// the line the last token ends on, if it is an operand, i.e. can be followed by a binary
+    /// operator
+    operand_line: Option<usize>,
+    /// where the positions behind the gap start, `usize::MAX` if there are none
+    gap: usize,
+    /// how far the text behind the gap moved since its positions went behind it
+    tail_bytes: isize,
+    tail_lines: isize,
 }
 
 impl Lexer {
@@ -41,18 +124,20 @@ impl Lexer {
     }
 
     pub fn from_bytes(buffer: Vec<u8>) -> Self {
-        let mut lines = Vec::new();
-        lines.push(0);
-        for (i, b) in buffer.iter().enumerate() {
-            if *b == b'\n' {
-                lines.push(i + 1);
-            }
-        }
+        let text = match String::from_utf8(buffer) {
+            Ok(text) => text,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        };
         let mut lexer = Lexer {
-            buffer,
-            lines,
+            buffer: Rope::from_str(&text),
+            window: text.into_bytes(),
+            window_start: 0,
             position: Default::default(),
-            tokens: Vec::new(),
+            tokens: GapVec::new(),
+            operand_line: None,
+            gap: usize::MAX,
+            tail_bytes: 0,
+            tail_lines: 0,
         };
         lexer.parse_tokens();
         lexer
@@ -65,74 +150,165 @@ impl Lexer {
 
     /// ---- getters -------------------------------------------------------------------------------
 
-    /// returns a vector of line start positions.
-    /// e.g. if you have lines with lengths (5, 7, 8) chars, `self.lines()` will return
-    /// [0, 5, 12]
-    pub fn lines(&self) -> &Vec<usize> {
-        &self.lines
+    pub fn get_text(&self) -> &Rope {
+        &self.buffer
     }
 
     pub fn len(&self) -> usize {
-        self.buffer.len()
+        self.buffer.len_bytes()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.buffer.len_lines()
+    }
+
+    /// the byte offset `line` starts at
+    pub fn line_start(&self, line: usize) -> usize {
+        self.buffer.line_to_byte(line.min(self.buffer.len_lines()))
+    }
+
+    /// where the gap is and how far the text behind it moved
+    fn tail(&self) -> Offset {
+        Offset {
+            from: self.gap,
+            to: usize::MAX,
+            bytes: self.tail_bytes,
+            lines: self.tail_lines,
+        }
+    }
+
+    /// the byte offset of `pos`
+    pub fn absolute(&self, pos: &CursorPosition) -> usize {
+        pos.resolve(&self.tail()).0
+    }
+
+    pub fn line(&self, pos: &CursorPosition) -> usize {
+        pos.resolve(&self.tail()).1
+    }
+
+    /// the byte offset of `pos` in its line
+    pub fn character(&self, pos: &CursorPosition) -> usize {
+        let (absolute, line) = pos.resolve(&self.tail());
+        absolute - self.line_start(line)
+    }
+
+    /// the position of the byte at `absolute`
+    pub fn position_at(&self, absolute: usize) -> CursorPosition {
+        let absolute = absolute.min(self.len());
+        let mut pos = CursorPosition::new(absolute, self.buffer.byte_to_line(absolute));
+        pos.shift(&self.tail());
+        pos
+    }
+
+    /// moves the gap behind the last token, the tokens compare with the ones of a lexer that never
+    /// had an edit then. Everything else holding positions has to follow the returned `Offset`
+    pub fn close_gap(&mut self) -> Offset {
+        let gap = self.tail();
+        let moved = self.tokens.gap()..self.tokens.len();
+        self.tokens.move_gap(self.tokens.len());
+        for index in moved {
+            if let Some(token) = self.tokens.get_mut(index) {
+                token.shift(&gap);
+            }
+        }
+        self.gap = usize::MAX;
+        self.tail_bytes = 0;
+        self.tail_lines = 0;
+        gap
     }
 
     pub fn cursor_pos_from_text_pos(&self, pos: Position) -> CursorPosition {
-        CursorPosition::new(
-            pos.character as usize + self.lines[pos.line as usize],
-            pos.line as usize,
-        )
+        let line = pos.line as usize;
+        if line >= self.line_count() {
+            return self.position_at(self.len());
+        }
+        self.position_at(self.line_start(line) + pos.character as usize)
     }
 
     pub fn cursor_pos_to_text_pos(&self, pos: CursorPosition) -> Position {
-        Position::new(pos.line() as u32, pos.character(&self.lines) as u32)
+        Position::new(self.line(&pos) as u32, self.character(&pos) as u32)
     }
 
-    /// advancing the CursorPosition `cursor` by `by` characters, taking into account line breaks
-    pub fn advance_cursor(&self, cursor: &mut CursorPosition, by: usize) {
-        let by_rest = by;
-        *cursor += by;
-        while self.lines[cursor.line()] < cursor.absolute() {
-            cursor.advance_line()
-        }
+    /// converts the range of `element` to an lsp `Range`
+    pub fn range_to_text_range<R: HasRange>(&self, element: &R) -> Range {
+        let range = element.get_range();
+        Range::new(
+            self.cursor_pos_to_text_pos(range.0),
+            self.cursor_pos_to_text_pos(range.1),
+        )
     }
 
-    pub fn get_token_byte(&self, position: &CursorPosition) -> &u8 {
-        &self.buffer[position.absolute()]
+    pub fn get_token_byte(&self, position: &CursorPosition) -> u8 {
+        self.buffer.byte(self.absolute(position))
     }
 
-    pub fn get_range_bytes<R: HasRange>(&self, element: &R) -> &[u8] {
+    /// the bytes `start..end`, borrowed from the window or the buffer unless they cross a chunk
+    /// of the rope
+    fn get_bytes(&self, start: usize, end: usize) -> Cow<'_, [u8]> {
+        let end = end.min(self.len());
+        let start = start.min(end);
+        if let Some(bytes) = start
+            .checked_sub(self.window_start)
+            .and_then(|s| self.window.get(s..end - self.window_start))
+        {
+            return Cow::Borrowed(bytes);
+        }
+        let (chunk, chunk_start, _, _) = self.buffer.chunk_at_byte(start);
+        match chunk.as_bytes().get(start - chunk_start..end - chunk_start) {
+            Some(bytes) => Cow::Borrowed(bytes),
+            None => Cow::Owned(self.buffer.bytes_at(start).take(end - start).collect()),
+        }
+    }
+
+    pub fn get_range_bytes<R: HasRange>(&self, element: &R) -> Cow<'_, [u8]> {
         let range = element.get_range();
-        &self.buffer[range.0.absolute()..range.1.absolute()]
+        self.get_bytes(self.absolute(&range.0), self.absolute(&range.1))
     }
 
     /// Compares the token at range with the given bytes, case insensitive
     pub fn compare_range<R: HasRange>(&self, element: &R, bytes: &[u8]) -> bool {
-        self.get_range_bytes(element).to_ascii_lowercase() == bytes
+        let range = self.get_range_bytes(element);
+        range.len() == bytes.len()
+            && range
+                .iter()
+                .zip(bytes)
+                .all(|(a, b)| a.to_ascii_lowercase() == *b)
     }
 
-    pub fn get_token_bytes(&self, token: &Token) -> &[u8] {
+    pub fn get_token_bytes(&self, token: &Token) -> Cow<'_, [u8]> {
         self.get_range_bytes(token)
     }
 
-    pub fn get_range_str<R: HasRange>(&self, token: &R) -> Cow<str> {
-        String::from_utf8_lossy(self.get_range_bytes(token))
+    pub fn get_range_str<R: HasRange>(&self, token: &R) -> Cow<'_, str> {
+        match self.get_range_bytes(token) {
+            Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+
+    /// the value of a `Token::Number`, `None` for any other token
+    pub fn get_number(&self, token: &Token) -> Option<f64> {
+        if let Token::Number(range) = token {
+            // fortran style exponents, `1d-3`
+            return self
+                .get_range_str(range)
+                .replace(['d', 'D'], "e")
+                .parse()
+                .ok();
+        }
+        None
     }
 
     pub fn format_position(&self, pos: &CursorPosition) -> String {
-        let pos = pos.absolute();
-        format!("{}", String::from_utf8_lossy(&self.buffer[pos..pos + 1]))
+        self.get_range_str(&(*pos, pos + 1)).into_owned()
     }
 
     pub fn format_range_ref(&self, range: &(CursorPosition, CursorPosition)) -> String {
-        let s = range.0.absolute();
-        let e = range.1.absolute();
-        format!("{}", String::from_utf8_lossy(&self.buffer[s..e]))
+        self.get_range_str(range).into_owned()
     }
 
     pub fn format_range(&self, range: &(CursorPosition, CursorPosition)) -> String {
-        let s = range.0.absolute();
-        let e = range.1.absolute();
-        format!("{}", String::from_utf8_lossy(&self.buffer[s..e]))
+        self.get_range_str(range).into_owned()
     }
 
     /// ---- printing ------------------------------------------------------------------------------
@@ -145,21 +321,146 @@ impl Lexer {
             Token::Ident((s, e)) => format!("Ident({})", self.format_range(&(*s, *e))),
             Token::Number((s, e)) => format!("Number({})", self.format_range(&(*s, *e))),
             Token::Operator(p) => self.format_position(p),
+            Token::Slash(p) => self.format_position(p),
+            Token::Caret(p) => self.format_position(p),
+            Token::Less(p) => self.format_position(p),
+            Token::Greater(p) => self.format_position(p),
+            Token::LessEqual(_)
+            | Token::GreaterEqual(_)
+            | Token::NotEqual(_)
+            | Token::And(_)
+            | Token::Or(_)
+            | Token::Arrow(_)
+            | Token::DoubleEqual(_)
+            | Token::ColonEqual(_) => self.format_range(&token.get_range()),
             Token::Equal(p) => self.format_position(p),
-            Token::ColonEqual(p) => self.format_range(&(*p, p + 1)),
             Token::Dot(p) => self.format_position(p),
             Token::SemiColon(p) => self.format_position(p),
             Token::Colon(p) => self.format_position(p),
             Token::Komma(p) => self.format_position(p),
-            Token::Quotes(p) => self.format_position(p),
-            Token::DoubleQuotes(p) => self.format_position(p),
+            Token::String((s, e)) => format!("String({})", self.format_range(&(*s, *e))),
             Token::Comment((s, e)) => format!("Comment({})", self.format_range(&(*s, *e))),
             Token::MultilineComment(_) => {
                 format!("Comment({})", self.format_range(&token.get_range()))
             }
             Token::Char(p) => self.format_position(p),
             Token::EOF => "EOF".to_string(),
-            Token::DoubleEqual(p) => self.format_position(p),
+        }
+    }
+
+    /// ---- editing -------------------------------------------------------------------------------
+    ///
+    /// replaces the bytes `start..end` by `text` and re-lexes only the tokens around the edit.
+    ///
+    /// Lexing restarts one token in front of the edit (it could grow into it, e.g. `a` -> `ab` or
+    /// `:` -> `:=`) and stops as soon as a token behind the edit matches an old one. The gap moves
+    /// to the start of the edit first and ends up in front of that old token, so the positions
+    /// behind it stay as they are.
+    pub fn edit(&mut self, start: usize, end: usize, text: &str) -> TokenEdit {
+        let mut first = self
+            .tokens
+            .partition_point(|t| self.absolute(&t.get_range().1) < start)
+            .saturating_sub(1);
+        // tokens without whitespace in between can merge into one, e.g. `1.0e` and `-7`
+        while first > 0
+            && self
+                .tokens
+                .get(first)
+                .is_some_and(|t| t.get_range().0 == self.tokens[first - 1].get_range().1)
+        {
+            first -= 1;
+        }
+        let (restart, restart_line) = self
+            .tokens
+            .get(first)
+            .map(|t| t.get_range().0.resolve(&self.tail()))
+            .filter(|(absolute, _)| *absolute <= start)
+            .unwrap_or_default();
+
+        // move the gap in front of the first re-lexed token
+        let gap = Offset {
+            from: self.gap.min(restart),
+            to: self.gap.max(restart),
+            ..self.tail()
+        };
+        let moved = self.tokens.gap().min(first)..self.tokens.gap().max(first);
+        self.tokens.move_gap(first);
+        for index in moved {
+            if let Some(token) = self.tokens.get_mut(index) {
+                token.shift(&gap);
+            }
+        }
+        self.gap = restart;
+
+        let removed_lines = self.buffer.byte_to_line(end) - self.buffer.byte_to_line(start);
+        let (char_start, char_end) = (
+            self.buffer.byte_to_char(start),
+            self.buffer.byte_to_char(end),
+        );
+        self.buffer.remove(char_start..char_end);
+        self.buffer.insert(char_start, text);
+        self.tail_bytes += text.len() as isize - (end - start) as isize;
+        self.tail_lines += text.matches('\n').count() as isize - removed_lines as isize;
+
+        self.position = CursorPosition::new(restart, restart_line);
+        self.operand_line = first
+            .checked_sub(1)
+            .and_then(|i| Self::get_operand_line(&self.tokens[i]));
+        self.window_start = restart;
+        self.window.clear();
+        self.grow_window();
+
+        let edit_end = start + text.len();
+        let (mut removed, mut inserted) = (0, 0);
+        let resync = loop {
+            let Some(token) = self.next_token_in_window() else {
+                break None;
+            };
+            if token == Token::EOF {
+                break None;
+            }
+            let token_start = self.absolute(&token.get_range().0);
+            while self.tokens.behind_gap().is_some_and(|t| {
+                let s = self.absolute(&t.get_range().0);
+                s < edit_end || s < token_start
+            }) {
+                self.tokens.remove_behind_gap();
+                removed += 1;
+            }
+            // the gap can't go between two tokens without whitespace in between
+            let touches = self.tokens.gap() > 0
+                && self.tokens[self.tokens.gap() - 1].get_range().1 == token.get_range().0;
+            if token_start >= edit_end && !touches {
+                if let Some(old) = self.tokens.behind_gap() {
+                    let mut old = old.clone();
+                    old.shift(&self.tail());
+                    if old == token {
+                        break Some(token_start);
+                    }
+                }
+            }
+            self.tokens.push(token);
+            inserted += 1;
+        };
+
+        match resync {
+            Some(gap) => self.gap = gap,
+            None => {
+                while self.tokens.remove_behind_gap().is_some() {
+                    removed += 1;
+                }
+                self.gap = usize::MAX;
+                self.tail_bytes = 0;
+                self.tail_lines = 0;
+            }
+        }
+
+        TokenEdit {
+            first,
+            removed,
+            inserted,
+            start: self.position_at(restart),
+            gap,
         }
     }
 
@@ -174,20 +475,61 @@ impl Lexer {
         }
     }
 
-    pub fn get_tokens(&self) -> &Vec<Token> {
+    pub fn get_tokens(&self) -> &GapVec<Token> {
         &self.tokens
     }
 
     pub fn next_token(&mut self) -> Option<Token> {
+        let token = self.read_token();
+        self.operand_line = token.as_ref().and_then(Self::get_operand_line);
+        token
+    }
+
+    /// reads the next token while lexing an edit, the window grows when the token could reach
+    /// beyond it
+    fn next_token_in_window(&mut self) -> Option<Token> {
+        loop {
+            let (position, operand_line) = (self.position, self.operand_line);
+            let token = self.next_token();
+            let window_end = self.window_start + self.window.len();
+            if window_end >= self.len() || self.offset() + LOOKAHEAD < window_end {
+                return token;
+            }
+            self.position = position;
+            self.operand_line = operand_line;
+            self.grow_window();
+        }
+    }
+
+    fn grow_window(&mut self) {
+        let from = (self.window_start + self.window.len()).min(self.len());
+        let grow = self.window.len().max(WINDOW);
+        self.window.extend(self.buffer.bytes_at(from).take(grow));
+    }
+
+    fn get_operand_line(token: &Token) -> Option<usize> {
+        match token {
+            Token::Ident(range) | Token::Number(range) | Token::String(range) => {
+                Some(range.1.front()?.1)
+            }
+            Token::ParentClose(p) => Some(p.front()?.1),
+            _ => None,
+        }
+    }
+
+    fn read_token(&mut self) -> Option<Token> {
         self.skip_whitespace();
 
         if let Some(p) = self.peak_char() {
-            if p.is_ascii_digit() {
+            if p.is_ascii_digit() || (p == b'.' && self.peak_digit(1)) {
                 return self.read_number();
             }
-            if p.is_ascii_alphanumeric() {
+            if p.is_ascii_alphanumeric() || p == b'_' {
                 return self.read_ident();
             }
+            if p == b'"' || p == b'\'' {
+                return self.read_string();
+            }
             let token = match p {
                 b'{' => Some(Token::BraceOpen(self.position)),
                 b'}' => Some(Token::BraceClose(self.position)),
@@ -198,16 +540,19 @@ impl Lexer {
                 b';' => Some(Token::SemiColon(self.position)),
                 b':' => self.read_colon(),
                 b'+' => Some(Token::Operator(self.position)),
-                b'-' => Some(Token::Operator(self.position)),
-                b'_' => Some(Token::Operator(self.position)),
+                b'-' => self.read_pair(&[(b'>', Token::Arrow)], Token::Operator),
                 b'*' => Some(Token::Operator(self.position)),
-                b'<' => Some(Token::Operator(self.position)),
-                b'>' => Some(Token::Operator(self.position)),
+                b'^' => Some(Token::Caret(self.position)),
+                b'<' => self.read_pair(
+                    &[(b'=', Token::LessEqual), (b'>', Token::NotEqual)],
+                    Token::Less,
+                ),
+                b'>' => self.read_pair(&[(b'=', Token::GreaterEqual)], Token::Greater),
+                b'&' => self.read_pair(&[(b'&', Token::And)], Token::Char),
+                b'|' => self.read_pair(&[(b'|', Token::Or)], Token::Char),
                 b'=' => self.read_equal(),
                 b'/' => self.read_forward_slash(),
-                b'\'' => Some(Token::Quotes(self.position)),
-                b'"' => Some(Token::DoubleQuotes(self.position)),
-                b'!' => self.read_comment(),
+                b'!' => self.read_exclamation(),
                 b'#' => {
                     let p0 = self.position;
                     self.position += 1;
@@ -229,20 +574,24 @@ impl Lexer {
     }
 
     /// ---- internal reading functions ------------------------------------------------------------
+    /// the byte offset of the lexer, it only lexes in front of the gap
+    fn offset(&self) -> usize {
+        self.position.front().unwrap_or_default().0
+    }
+
     fn next_char(&mut self) -> Option<u8> {
-        if self.position.absolute() >= self.buffer.len() {
-            return None;
-        }
-        let c = self.buffer[self.position.absolute()];
+        let c = self.peak_char()?;
         self.position += 1; // position is now one character ahead
         Some(c)
     }
 
     fn peak_char(&self) -> Option<u8> {
-        if self.position.absolute() >= self.buffer.len() {
-            return None;
-        }
-        Some(self.buffer[self.position.absolute()])
+        self.peak_char_at(0)
+    }
+
+    fn peak_char_at(&self, ahead: usize) -> Option<u8> {
+        let index = (self.offset() + ahead).checked_sub(self.window_start)?;
+        self.window.get(index).copied()
     }
 
     fn skip_whitespace(&mut self) {
@@ -257,11 +606,24 @@ impl Lexer {
         }
     }
 
+    /// reads a two character operator, made of the current character and one of the characters in
+    /// `pairs`, or the single character `single`
+    fn read_pair(&mut self, pairs: &[(u8, PositionToken)], single: PositionToken) -> Option<Token> {
+        let p1 = self.position;
+        let next = self.peak_char_at(1);
+        if let Some((_, pair)) = pairs.iter().find(|(c, _)| Some(*c) == next) {
+            self.position += 1;
+            return Some(pair(p1));
+        }
+        Some(single(p1))
+    }
+
     pub fn read_colon(&mut self) -> Option<Token> {
+        let p1 = self.position;
         self.position += 1;
 
         if let Some(b'=') = self.peak_char() {
-            Some(Token::ColonEqual(self.position))
+            Some(Token::ColonEqual(p1))
         } else {
             self.position -= 1;
             Some(Token::Colon(self.position))
@@ -279,6 +641,7 @@ impl Lexer {
         } else if let Some(b'*') = self.peak_char() {
             let mut start = p1;
             let mut lines = Vec::new();
+            let mut closed = false;
             self.position += 1;
 
             while let Some(c) = self.peak_char() {
@@ -291,15 +654,36 @@ impl Lexer {
                     self.position += 1;
                     if let Some(b'/') = self.peak_char() {
                         lines.push((start, self.position + 1));
+                        closed = true;
                         break;
                     }
                 }
                 self.position += 1;
             }
+            // unclosed comment, it runs until EOF
+            if !closed {
+                lines.push((start, self.position));
+            }
             return Some(Token::MultilineComment(lines));
         }
         self.position -= 1;
-        Some(Token::Operator(p1))
+        Some(Token::Slash(p1))
+    }
+
+    /// `!=` following an operand on the same line is an operator, anything else starting with `!`
+    /// is a comment, including banners like `!=====` or `!=-=-=` and notes like `!= see below`
+    fn read_exclamation(&mut self) -> Option<Token> {
+        let (_, line) = self.position.front().unwrap_or_default();
+        let after_operand = self.operand_line == Some(line);
+        if after_operand
+            && self.peak_char_at(1) == Some(b'=')
+            && self.peak_char_at(2) != Some(b'=')
+        {
+            let p1 = self.position;
+            self.position += 1;
+            return Some(Token::NotEqual(p1));
+        }
+        self.read_comment()
     }
 
     pub fn read_comment(&mut self) -> Option<Token> {
@@ -315,15 +699,73 @@ impl Lexer {
         Some(Token::Comment((p1, end)))
     }
 
+    /// reads a number like `1`, `1.5`, `.5` or `1.`, with an optional exponent starting with `e`
+    /// or `d` in either case, like `1.0e-7`, `1d-3` or `2E+10`
     pub fn read_number(&mut self) -> Option<Token> {
         let p1 = self.position;
+        self.skip_digits();
+        if let Some(b'.') = self.peak_char() {
+            self.position += 1;
+            self.skip_digits();
+        }
+        if let Some(b'e' | b'E' | b'd' | b'D') = self.peak_char() {
+            let sign = matches!(self.peak_char_at(1), Some(b'+' | b'-')) as usize;
+            // without digits, the `e` starts the next token
+            if self.peak_digit(1 + sign) {
+                self.position += 1 + sign;
+                self.skip_digits();
+            }
+        }
+        Some(Token::Number((p1, self.position)))
+    }
+
+    fn skip_digits(&mut self) {
+        while self.peak_digit(0) {
+            self.position += 1;
+        }
+    }
+
+    /// whether the character `ahead` characters after the current position is a digit
+    fn peak_digit(&self, ahead: usize) -> bool {
+        matches!(self.peak_char_at(ahead), Some(c) if c.is_ascii_digit())
+    }
+
+    /// reads a string up to and including the closing quote, a backslash escapes the next
+    /// character. An unterminated string ends with its line
+    pub fn read_string(&mut self) -> Option<Token> {
+        let p1 = self.position;
+        let quote = self.next_char()?;
         while let Some(c) = self.peak_char() {
-            if !c.is_ascii_digit() && c != b'.' {
+            if c == b'\n' {
                 break;
             }
             self.position += 1;
+            if c == quote {
+                break;
+            }
+            if c == b'\\' && self.peak_char().is_some_and(|c| c != b'\n') {
+                self.position += 1;
+            }
         }
-        Some(Token::Number((p1, self.position)))
+        Some(Token::String((p1, self.position)))
+    }
+
+    /// the range between the quotes of a string, an unterminated string has no closing quote
+    pub fn get_string_contents(
+        &self,
+        range: &(CursorPosition, CursorPosition),
+    ) -> (CursorPosition, CursorPosition) {
+        let (start, end) = *range;
+        let bytes = self.get_range_bytes(range);
+        let mut i = 1;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                c if c == bytes[0] => return (start + 1, start + i),
+                _ => i += 1,
+            }
+        }
+        (start + 1, end)
     }
 
     pub fn read_ident(&mut self) -> Option<Token> {
@@ -350,21 +792,11 @@ impl Lexer {
 
 impl Display for Lexer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.buffer.len() > 1000 {
-            write!(
-                f,
-                "{}\n ...",
-                &String::from_utf8(self.buffer[0..1000].to_vec())
-                    .unwrap_or_else(|_| UTF8_PARSER_MSG.to_string())
-            )?;
-        } else {
-            write!(
-                f,
-                "{}\n ...",
-                &String::from_utf8(self.buffer.to_vec())
-                    .unwrap_or_else(|_| UTF8_PARSER_MSG.to_string())
-            )?;
-        }
+        write!(
+            f,
+            "{}\n ...",
+            String::from_utf8_lossy(&self.get_bytes(0, 1000))
+        )?;
         for token in self.tokens.iter() {
             writeln!(f, "{}", self.format_token(&token))?;
         }
@@ -397,6 +829,8 @@ mod tests {
 
         assert_eq!(tokens.len(), 3);
         assert!(tokens[1].is_assignment());
+        assert_eq!(lexer.get_range_str(&tokens[1]), ":=");
+        assert_eq!(lexer.get_range_str(&tokens[2]), "b");
     }
 
     #[test]
@@ -423,6 +857,177 @@ mod tests {
         check_string(b"123", &["Number(123)"]);
     }
 
+    #[test]
+    fn match_string() {
+        check_string(
+            br#"print, text="done!"; call, file='//afs/a.madx' ; "a\"b" 'c"d' "open"#,
+            &[
+                "Ident(print)",
+                ",",
+                "Ident(text)",
+                "=",
+                "String(\"done!\")",
+                ";",
+                "Ident(call)",
+                ",",
+                "Ident(file)",
+                "=",
+                "String('//afs/a.madx')",
+                ";",
+                r#"String("a\"b")"#,
+                r#"String('c"d')"#,
+                r#"String("open)"#,
+            ],
+        );
+
+        let lexer = Lexer::from_str("'abc' \"a\\\"b\" \"open\n");
+        let contents: Vec<_> = lexer
+            .get_tokens()
+            .iter()
+            .map(|t| lexer.get_range_str(&lexer.get_string_contents(&t.get_range())))
+            .collect();
+        assert_eq!(contents, ["abc", "a\\\"b", "open"]);
+    }
+
+    #[test]
+    fn match_operators() {
+        check_string(
+            b"if (a <> b && c >= 1) x = mq->k1 ^ 2 / y;",
+            &[
+                "Ident(if)",
+                "(",
+                "Ident(a)",
+                "<>",
+                "Ident(b)",
+                "&&",
+                "Ident(c)",
+                ">=",
+                "Number(1)",
+                ")",
+                "Ident(x)",
+                "=",
+                "Ident(mq)",
+                "->",
+                "Ident(k1)",
+                "^",
+                "Number(2)",
+                "/",
+                "Ident(y)",
+                ";",
+            ],
+        );
+        check_string(
+            b"a != b || c <= d < e > f - g",
+            &[
+                "Ident(a)", "!=", "Ident(b)", "||", "Ident(c)", "<=", "Ident(d)", "<", "Ident(e)",
+                ">", "Ident(f)", "-", "Ident(g)",
+            ],
+        );
+        check_string(
+            b"a := b == c",
+            &["Ident(a)", ":=", "Ident(b)", "==", "Ident(c)"],
+        );
+
+        let lexer = Lexer::from_str("a != b; !== banner\n");
+        let tokens = lexer.get_tokens();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[1], Token::NotEqual(tokens[1].get_range().0));
+        assert_eq!(lexer.format_token(&tokens[4]), "Comment(!== banner)");
+
+        // without an operand in front, `!=` starts a comment
+        let lexer = Lexer::from_str("!=-=-=-=-\n!= see below\na = 1; != note\nb = (c)\n!= d;");
+        let tokens: Vec<_> = lexer
+            .get_tokens()
+            .iter()
+            .map(|t| lexer.format_token(t))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                "Comment(!=-=-=-=-)",
+                "Comment(!= see below)",
+                "Ident(a)",
+                "=",
+                "Number(1)",
+                ";",
+                "Comment(!= note)",
+                "Ident(b)",
+                "=",
+                "(",
+                "Ident(c)",
+                ")",
+                "Comment(!= d;)",
+            ]
+        );
+    }
+
+    #[test]
+    fn match_underscore_ident() {
+        check_string(
+            b"_a b_ _c_ on_x;",
+            &["Ident(_a)", "Ident(b_)", "Ident(_c_)", "Ident(on_x)", ";"],
+        );
+        assert_eq!(Lexer::from_str("_a b_ _c_").get_tokens().len(), 3);
+    }
+
+    #[test]
+    fn match_scientific_number() {
+        check_string(
+            b"1.0e-7 .5 1d-3 2E+10 3. 4e x",
+            &[
+                "Number(1.0e-7)",
+                "Number(.5)",
+                "Number(1d-3)",
+                "Number(2E+10)",
+                "Number(3.)",
+                "Number(4)",
+                "Ident(e)",
+                "Ident(x)",
+            ],
+        );
+
+        let lexer = Lexer::from_str("1.0e-7 .5 1d-3 2E+10 3.");
+        let numbers: Vec<_> = lexer
+            .get_tokens()
+            .iter()
+            .map(|t| lexer.get_number(t))
+            .collect();
+        assert_eq!(
+            numbers,
+            [Some(1e-7), Some(0.5), Some(1e-3), Some(2e10), Some(3.0)]
+        );
+    }
+
+    #[test]
+    fn numbers_in_model_job() {
+        let lexer = Lexer::from_bytes(include_bytes!("../../tests/job.create_model.madx").to_vec());
+        let tokens = lexer.get_tokens();
+
+        // `START, X=1.0e-7, Y=1.0e-7;`
+        let start = tokens
+            .iter()
+            .position(|t| lexer.get_range_str(t) == "START")
+            .unwrap();
+        let values: Vec<_> = tokens
+            .iter()
+            .skip(start)
+            .take(9)
+            .filter_map(|t| lexer.get_number(t))
+            .collect();
+        assert_eq!(values, [1e-7, 1e-7]);
+        assert_eq!(lexer.format_token(&tokens[start + 4]), "Number(1.0e-7)");
+
+        for token in tokens.iter() {
+            if let Token::Number(_) = token {
+                assert!(
+                    lexer.get_number(token).is_some(),
+                    "{}",
+                    lexer.format_token(token)
+                );
+            }
+        }
+    }
+
     #[test]
     fn match_option() {
         check_string(
@@ -455,10 +1060,7 @@ mod tests {
                 ";",
                 "Ident(system)",
                 ",",
-                "\"",
-                "Ident(mkdir)",
-                "Ident(temp)",
-                "\"",
+                "String(\"mkdir temp\")",
                 ";",
             ],
         );
@@ -479,10 +1081,10 @@ mod tests {
         let lexer = Lexer::from_str("first;\n// this is a comment\nsecond");
         let tokens = lexer.get_tokens();
 
-        assert_eq!(lexer.get_token_bytes(&tokens[0]), b"first");
-        assert_eq!(lexer.get_token_bytes(&tokens[1]), b";");
-        assert_eq!(lexer.get_token_bytes(&tokens[2]), b"// this is a comment");
-        assert_eq!(lexer.get_token_bytes(&tokens[3]), b"second");
+        assert_eq!(*lexer.get_token_bytes(&tokens[0]), *b"first");
+        assert_eq!(*lexer.get_token_bytes(&tokens[1]), *b";");
+        assert_eq!(*lexer.get_token_bytes(&tokens[2]), *b"// this is a comment");
+        assert_eq!(*lexer.get_token_bytes(&tokens[3]), *b"second");
     }
 
     #[test]
@@ -510,6 +1112,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn unclosed_multiline_comment() {
+        let lexer = Lexer::from_str("first;\n/* this comment\nis never closed");
+        let tokens = lexer.get_tokens();
+
+        assert_eq!(tokens.len(), 3);
+        if let Token::MultilineComment(v) = &tokens[2] {
+            assert_eq!(v.len(), 2);
+            assert_eq!(lexer.get_range_str(&v[0]), "/* this comment");
+            assert_eq!(lexer.get_range_str(&v[1]), "is never closed");
+        } else {
+            assert!(false, "Expected multiline comment");
+        }
+    }
+
     #[test]
     fn underscore_at_end() {
         let lexer = Lexer::from_str("exec, a_");
@@ -549,4 +1166,39 @@ mod tests {
             assert!(false, "Expected Equal");
         }
     }
+
+    #[test]
+    fn edit_relexes_only_around_the_edit() {
+        let mut lexer = Lexer::from_str(&"a = 1;\n".repeat(100));
+        let edit = lexer.edit(403, 404, "42\n");
+        // `=` in front of the edit and the number
+        assert_eq!((edit.first, edit.removed, edit.inserted), (229, 2, 2));
+        // the tokens behind the edit stay behind the gap
+        assert_eq!(lexer.get_tokens().gap(), 231);
+
+        let expected =
+            Lexer::from_str(&("a = 1;\n".repeat(57) + "a = 42\n;\n" + &"a = 1;\n".repeat(42)));
+        assert_eq!(lexer.get_text(), expected.get_text());
+        lexer.close_gap();
+        assert_eq!(lexer.get_tokens(), expected.get_tokens());
+
+        // the gap moves back in front of an earlier edit and behind a later one
+        for (start, end, text) in [(7, 8, ""), (694, 694, "b = 2;\n")] {
+            lexer.edit(start, end, text);
+        }
+        let expected = Lexer::from_str(
+            &("a = 1;\n = 1;\n".to_string()
+                + &"a = 1;\n".repeat(55)
+                + "a = 42\n;\n"
+                + &"a = 1;\n".repeat(41)
+                + "b = 2;\na = 1;\n"),
+        );
+        assert_eq!(lexer.get_text(), expected.get_text());
+        assert_eq!(
+            lexer.cursor_pos_to_text_pos(lexer.get_tokens()[400].get_range().0),
+            Position::new(101, 2)
+        );
+        lexer.close_gap();
+        assert_eq!(lexer.get_tokens(), expected.get_tokens());
+    }
 }
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use super::{CursorPosition, HasRange};
+use super::{CursorPosition, HasRange, Offset, Shift};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
@@ -10,7 +10,21 @@ pub enum Token {
     ParentClose(CursorPosition),
     Ident((CursorPosition, CursorPosition)),
     Number((CursorPosition, CursorPosition)),
+    /// `+`, `-` or `*`
     Operator(CursorPosition),
+    /// the division `/`
+    Slash(CursorPosition),
+    Caret(CursorPosition),
+    Less(CursorPosition),
+    Greater(CursorPosition),
+    LessEqual(CursorPosition),
+    GreaterEqual(CursorPosition),
+    /// `<>` or `!=`
+    NotEqual(CursorPosition),
+    And(CursorPosition),
+    Or(CursorPosition),
+    /// attribute access, `->`
+    Arrow(CursorPosition),
     Equal(CursorPosition),
     DoubleEqual(CursorPosition),
     ColonEqual(CursorPosition),
@@ -18,8 +32,8 @@ pub enum Token {
     SemiColon(CursorPosition),
     Colon(CursorPosition),
     Komma(CursorPosition),
-    Quotes(CursorPosition),
-    DoubleQuotes(CursorPosition),
+    /// a string literal including its quotes, either `"` or `'`
+    String((CursorPosition, CursorPosition)),
     Comment((CursorPosition, CursorPosition)),
     MultilineComment(Vec<(CursorPosition, CursorPosition)>),
     Char(CursorPosition),
@@ -36,14 +50,23 @@ impl HasRange for Token {
             Token::Ident((s, e)) => (*s, *e),
             Token::Number((s, e)) => (*s, *e),
             Token::Operator(p) => (*p, p + 1),
+            Token::Slash(p) => (*p, p + 1),
+            Token::Caret(p) => (*p, p + 1),
+            Token::Less(p) => (*p, p + 1),
+            Token::Greater(p) => (*p, p + 1),
+            Token::LessEqual(p) => (*p, p + 2),
+            Token::GreaterEqual(p) => (*p, p + 2),
+            Token::NotEqual(p) => (*p, p + 2),
+            Token::And(p) => (*p, p + 2),
+            Token::Or(p) => (*p, p + 2),
+            Token::Arrow(p) => (*p, p + 2),
             Token::Equal(p) => (*p, p + 1),
             Token::ColonEqual(p) => (*p, p + 2),
             Token::Dot(p) => (*p, p + 1),
             Token::SemiColon(p) => (*p, p + 1),
             Token::Colon(p) => (*p, p + 1),
             Token::Komma(p) => (*p, p + 1),
-            Token::Quotes(p) => (*p, p + 1),
-            Token::DoubleQuotes(p) => (*p, p + 1),
+            Token::String((s, e)) => (*s, *e),
             Token::Comment((s, e)) => (*s, *e),
             Token::MultilineComment(v) => (v[0].0, v.last().unwrap().1),
             Token::Char(p) => (*p, p + 1),
@@ -53,6 +76,42 @@ impl HasRange for Token {
     }
 }
 
+impl Shift for Token {
+    fn shift(&mut self, offset: &Offset) {
+        match self {
+            Token::BraceOpen(p) => p.shift(offset),
+            Token::BraceClose(p) => p.shift(offset),
+            Token::ParentOpen(p) => p.shift(offset),
+            Token::ParentClose(p) => p.shift(offset),
+            Token::Ident(r) => r.shift(offset),
+            Token::Number(r) => r.shift(offset),
+            Token::Operator(p) => p.shift(offset),
+            Token::Slash(p) => p.shift(offset),
+            Token::Caret(p) => p.shift(offset),
+            Token::Less(p) => p.shift(offset),
+            Token::Greater(p) => p.shift(offset),
+            Token::LessEqual(p) => p.shift(offset),
+            Token::GreaterEqual(p) => p.shift(offset),
+            Token::NotEqual(p) => p.shift(offset),
+            Token::And(p) => p.shift(offset),
+            Token::Or(p) => p.shift(offset),
+            Token::Arrow(p) => p.shift(offset),
+            Token::Equal(p) => p.shift(offset),
+            Token::DoubleEqual(p) => p.shift(offset),
+            Token::ColonEqual(p) => p.shift(offset),
+            Token::Dot(p) => p.shift(offset),
+            Token::SemiColon(p) => p.shift(offset),
+            Token::Colon(p) => p.shift(offset),
+            Token::Komma(p) => p.shift(offset),
+            Token::String(r) => r.shift(offset),
+            Token::Comment(r) => r.shift(offset),
+            Token::MultilineComment(v) => v.shift(offset),
+            Token::Char(p) => p.shift(offset),
+            Token::EOF => {}
+        }
+    }
+}
+
 impl Default for Token {
     fn default() -> Self {
         Token::EOF
@@ -1,9 +1,16 @@
+pub mod call_hierarchy;
 pub mod document;
+pub mod document_links;
+pub mod document_symbols;
 pub mod error;
+pub mod folding_ranges;
+pub mod inlay_hints;
 pub mod lexer;
 pub mod parser;
 pub mod rules;
 pub mod semantic_tokens;
+pub mod signature_help;
+pub mod type_hierarchy;
 pub mod visitor;
 
 pub mod debug;
@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use clap::Parser;
 use dashmap::DashMap;
+use inlay_hints::get_parameter_hints;
 use log::LevelFilter;
 use log4rs::append::file::FileAppender;
 use log4rs::config::Appender;
@@ -10,17 +13,28 @@ use log4rs::encode::pattern::PatternEncoder;
 use log4rs::Config;
 use parser::MaybeProblem;
 use parser::Problem;
-use parser::LEGEND_TYPE;
-use tower_lsp::jsonrpc::Result;
+use parser::GENERIC_BUILTINS;
+use parser::{is_builtin, is_builtin_constant, is_keyword};
+use parser::{LEGEND_MODIFIER, LEGEND_TYPE};
+use signature_help::CallContext;
+use tower_lsp::jsonrpc::{Error, Result};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
+use type_hierarchy::{format_inherited_attributes, get_builtin_attributes, get_builtin_item};
 
+pub mod call_hierarchy;
 pub mod document;
+pub mod document_links;
+pub mod document_symbols;
 pub mod error;
+pub mod folding_ranges;
+pub mod inlay_hints;
 pub mod lexer;
 pub mod parser;
 pub mod rules;
 pub mod semantic_tokens;
+pub mod signature_help;
+pub mod type_hierarchy;
 pub mod visitor;
 
 pub mod debug;
@@ -70,16 +84,26 @@ pub enum Message {
 struct Backend {
     documents: Arc<DashMap<Url, document::Document>>,
     client: Client,
+    /// the client pulls the diagnostics itself, so they are not published
+    pull_diagnostics: AtomicBool,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let pull_diagnostics = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|t| t.diagnostic.is_some());
+        self.pull_diagnostics
+            .store(pull_diagnostics, Ordering::Relaxed);
+
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(CompletionOptions {
                     resolve_provider: Some(false),
@@ -92,6 +116,38 @@ impl LanguageServer for Backend {
                     },
                 })),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
+                    DiagnosticOptions {
+                        identifier: Some("madxls".to_string()),
+                        inter_file_dependencies: true,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: WorkDoneProgressOptions::default(),
+                    },
+                )),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
                         SemanticTokensRegistrationOptions {
@@ -108,10 +164,10 @@ impl LanguageServer for Backend {
                                 work_done_progress_options: WorkDoneProgressOptions::default(),
                                 legend: SemanticTokensLegend {
                                     token_types: LEGEND_TYPE.into(),
-                                    token_modifiers: vec![],
+                                    token_modifiers: LEGEND_MODIFIER.into(),
                                 },
                                 range: Some(true),
-                                full: Some(SemanticTokensFullOptions::Bool(true)),
+                                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             },
                             static_registration_options: StaticRegistrationOptions::default(),
                         },
@@ -128,6 +184,27 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "server initialized!")
             .await;
+
+        // the server capabilities of lsp-types don't know about the type hierarchy yet,
+        // so it has to be registered dynamically
+        let options = TypeHierarchyRegistrationOptions {
+            text_document_registration_options: TextDocumentRegistrationOptions {
+                document_selector: Some(vec![DocumentFilter {
+                    language: Some("madx".to_string()),
+                    scheme: Some("file".to_string()),
+                    pattern: None,
+                }]),
+            },
+            ..Default::default()
+        };
+        let registration = Registration {
+            id: "type-hierarchy".to_string(),
+            method: "textDocument/prepareTypeHierarchy".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            log::error!("couldn't register type hierarchy: {}", e);
+        }
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
@@ -171,28 +248,33 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "did open!")
             .await;
+        // the document might already be loaded as an include, but the edits that follow refer to
+        // the text of the client
         let uri = &params.text_document.uri;
-        if !self.documents.contains_key(uri) {
-            let document =
-                document::Document::new(Some(uri.clone()), params.text_document.text.as_bytes());
+        let document =
+            document::Document::new(Some(uri.clone()), params.text_document.text.as_bytes());
 
-            // check the includes
-            let includes = document.parser.includes.clone();
-            let docs = self.documents.clone();
-            tokio::spawn(async move {
-                for incl in includes.into_iter() {
-                    reload_includes(incl, &docs);
-                }
-            });
+        // check the includes
+        let includes = document.parser.includes.clone();
+        let docs = self.documents.clone();
+        tokio::spawn(async move {
+            for incl in includes.into_iter() {
+                reload_includes(incl, &docs);
+            }
+        });
 
-            self.documents.insert(uri.clone(), document);
-        }
+        self.documents.insert(uri.clone(), document);
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         log::info!("did change");
         if let Some(mut document) = self.documents.get_mut(&params.text_document.uri) {
-            document.reload(params.content_changes[0].text.as_bytes());
+            for change in params.content_changes.iter() {
+                match change.range {
+                    Some(range) => document.edit(range, &change.text),
+                    None => document.reload(change.text.as_bytes()),
+                }
+            }
             //self.client.publish_diagnostics(params.text_document.uri.clone(), document.get_diagnostics(), None).await;
             // check the includes
             let includes = document.parser.includes.clone();
@@ -212,8 +294,6 @@ impl LanguageServer for Backend {
             .documents
             .get(&params.text_document_position_params.text_document.uri)
         {
-            self.resubmit_diagnostics(&params.text_document_position_params.text_document.uri)
-                .await;
             let labels = doc.get_labels_under_cursor(params.text_document_position_params.position);
             log::debug!("check hover for: {:?}", labels);
             let mut items = Vec::new();
@@ -231,6 +311,19 @@ impl LanguageServer for Backend {
                 log::debug!("checking in {}", uri.path());
                 incl.get_hover(&labels, &mut items, Some(uri));
             }
+
+            for label in labels.iter() {
+                let ancestors = get_ancestors(
+                    &label.to_ascii_lowercase(),
+                    &params.text_document_position_params.text_document.uri,
+                    &self.documents,
+                );
+                if !ancestors.is_empty() {
+                    items.push(MarkedString::String(format_inherited_attributes(
+                        &ancestors,
+                    )));
+                }
+            }
             return Ok(Some(Hover {
                 contents: HoverContents::Array(items),
                 range: None,
@@ -239,17 +332,513 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        log::info!("signature help");
+        let uri = &params.text_document_position_params.text_document.uri;
+        let Some(context) = self
+            .documents
+            .get(uri)
+            .and_then(|doc| doc.get_call_context(params.text_document_position_params.position))
+        else {
+            return Ok(None);
+        };
+        log::debug!("call context: {:?}", context);
+
+        let signature = match context {
+            CallContext::Macro { name, active } => get_reachable_uris(uri, &self.documents)
+                .iter()
+                .filter_map(|uri| self.documents.get(uri))
+                .find_map(|doc| doc.get_macro_signature(&name, active)),
+            CallContext::Builtin { name, attribute } => GENERIC_BUILTINS
+                .get(name.as_slice())
+                .map(|builder| builder.get_signature(attribute.as_deref())),
+        };
+
+        Ok(signature.map(|signature| SignatureHelp {
+            active_parameter: signature.active_parameter,
+            signatures: vec![signature],
+            active_signature: Some(0),
+        }))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        log::info!("inlay hint");
+        let uri = &params.text_document.uri;
+        let Some(calls) = self
+            .documents
+            .get(uri)
+            .map(|doc| doc.get_exec_calls(params.range))
+        else {
+            return Ok(None);
+        };
+
+        let reachable_uris = get_reachable_uris(uri, &self.documents);
+        let mut hints = Vec::new();
+        for call in calls.iter() {
+            if let Some(args) = reachable_uris
+                .iter()
+                .filter_map(|uri| self.documents.get(uri))
+                .find_map(|doc| doc.get_macro_args(&call.callee))
+            {
+                hints.extend(get_parameter_hints(call, &args));
+            }
+        }
+        Ok(Some(hints))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        log::info!("prepare call hierarchy");
+        let uri = &params.text_document_position_params.text_document.uri;
+        let Some(name) = self.documents.get(uri).and_then(|doc| {
+            doc.get_symbol_under_cursor(params.text_document_position_params.position)
+        }) else {
+            return Ok(None);
+        };
+
+        Ok(get_reachable_uris(uri, &self.documents)
+            .iter()
+            .filter_map(|uri| Some((uri, self.documents.get(uri)?)))
+            .find_map(|(uri, doc)| doc.get_call_hierarchy_item(&name, uri))
+            .map(|item| vec![item]))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        log::info!("incoming calls of {}", params.item.name);
+        if params.item.kind == SymbolKind::FILE {
+            return Ok(Some(Vec::new()));
+        }
+        let name = params.item.name.to_ascii_lowercase();
+        Ok(Some(
+            self.documents
+                .iter()
+                .flat_map(|entry| {
+                    entry
+                        .value()
+                        .get_incoming_calls(name.as_bytes(), entry.key())
+                })
+                .collect(),
+        ))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        log::info!("outgoing calls of {}", params.item.name);
+        let uri = &params.item.uri;
+        let Some(calls) = self.documents.get(uri).map(|doc| {
+            if params.item.kind == SymbolKind::FILE {
+                doc.get_top_level_calls()
+            } else {
+                doc.get_outgoing_calls(params.item.name.to_ascii_lowercase().as_bytes())
+            }
+        }) else {
+            return Ok(None);
+        };
+
+        let reachable_uris = get_reachable_uris(uri, &self.documents);
+        Ok(Some(
+            calls
+                .into_iter()
+                .filter_map(|(callee, from_ranges)| {
+                    let to = reachable_uris
+                        .iter()
+                        .filter_map(|uri| Some((uri, self.documents.get(uri)?)))
+                        .find_map(|(uri, doc)| doc.get_call_hierarchy_item(&callee, uri))?;
+                    Some(CallHierarchyOutgoingCall { to, from_ranges })
+                })
+                .collect(),
+        ))
+    }
+
+    async fn prepare_type_hierarchy(
+        &self,
+        params: TypeHierarchyPrepareParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>> {
+        log::info!("prepare type hierarchy");
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let Some(name) = self
+            .documents
+            .get(uri)
+            .and_then(|doc| doc.get_symbol_under_cursor(position))
+        else {
+            return Ok(None);
+        };
+
+        let reachable_uris = get_reachable_uris(uri, &self.documents);
+        Ok(
+            find_type_hierarchy_item(&name, &reachable_uris, &self.documents)
+                .or_else(|| {
+                    GENERIC_BUILTINS.contains_key(name.as_slice()).then(|| {
+                        get_builtin_item(
+                            String::from_utf8_lossy(&name).to_string(),
+                            uri,
+                            Range::new(position, position),
+                        )
+                    })
+                })
+                .map(|item| vec![item]),
+        )
+    }
+
+    async fn supertypes(
+        &self,
+        params: TypeHierarchySupertypesParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>> {
+        log::info!("supertypes of {}", params.item.name);
+        let name = params.item.name.to_ascii_lowercase();
+        let reachable_uris = get_reachable_uris(&params.item.uri, &self.documents);
+        let Some((uri, (class, class_range, _))) = reachable_uris
+            .iter()
+            .filter_map(|uri| Some((uri, self.documents.get(uri)?)))
+            .find_map(|(uri, doc)| Some((uri, doc.get_element_definition(name.as_bytes())?)))
+        else {
+            return Ok(Some(Vec::new()));
+        };
+
+        let parent =
+            find_type_hierarchy_item(&class, &reachable_uris, &self.documents).or_else(|| {
+                GENERIC_BUILTINS.contains_key(class.as_slice()).then(|| {
+                    get_builtin_item(
+                        String::from_utf8_lossy(&class).to_string(),
+                        uri,
+                        class_range,
+                    )
+                })
+            });
+        Ok(Some(parent.into_iter().collect()))
+    }
+
+    async fn subtypes(
+        &self,
+        params: TypeHierarchySubtypesParams,
+    ) -> Result<Option<Vec<TypeHierarchyItem>>> {
+        log::info!("subtypes of {}", params.item.name);
+        let name = params.item.name.to_ascii_lowercase();
+        Ok(Some(
+            self.documents
+                .iter()
+                .flat_map(|entry| entry.value().get_subtypes(name.as_bytes(), entry.key()))
+                .collect(),
+        ))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        log::info!("code lens");
+        let Some(definitions) = self
+            .documents
+            .get(&params.text_document.uri)
+            .map(|doc| doc.get_top_level_definitions())
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            definitions
+                .into_iter()
+                .map(|(name, range)| {
+                    let count: usize = self
+                        .documents
+                        .iter()
+                        .map(|entry| entry.value().count_references(&name))
+                        .sum();
+                    get_reference_lens(range, count)
+                })
+                .collect(),
+        ))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        log::info!("goto definition");
+        let uri = &params.text_document_position_params.text_document.uri;
+        if let Some(doc) = self.documents.get(uri) {
+            let labels = doc.get_labels_under_cursor(params.text_document_position_params.position);
+            log::debug!("look for definition of: {:?}", labels);
+
+            let mut locations = doc.get_definitions(&labels, uri);
+            for incl_uri in get_reachable_uris(uri, &self.documents).iter().skip(1) {
+                if let Some(incl) = self.documents.get(incl_uri) {
+                    locations.extend(incl.get_definitions(&labels, incl_uri));
+                }
+            }
+            return Ok(Some(GotoDefinitionResponse::Array(locations)));
+        }
+        Ok(None)
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        log::info!("references");
+        let uri = &params.text_document_position.text_document.uri;
+        let Some(name) = self
+            .documents
+            .get(uri)
+            .and_then(|doc| doc.get_symbol_under_cursor(params.text_document_position.position))
+        else {
+            return Ok(None);
+        };
+
+        let mut locations = Vec::new();
+        for entry in self.documents.iter() {
+            locations.extend(entry.value().get_references(
+                &name,
+                params.context.include_declaration,
+                entry.key(),
+            ));
+        }
+        Ok(Some(locations))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        log::info!("prepare rename");
+        let Some(doc) = self.documents.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        let pos = doc.parser.lexer.cursor_pos_from_text_pos(params.position);
+        let Some(token) = doc.parser.get_token_at(pos).filter(|t| t.is_ident()) else {
+            return Ok(None);
+        };
+        check_renamable(&doc.parser.get_element_bytes(token).to_ascii_lowercase())?;
+        Ok(Some(PrepareRenameResponse::Range(
+            doc.parser.lexer.range_to_text_range(token),
+        )))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        log::info!("rename");
+        check_new_name(&params.new_name)?;
+        let uri = &params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        if let Some(ranges) = self
+            .documents
+            .get(uri)
+            .and_then(|doc| doc.get_macro_arg_ranges(position))
+        {
+            let edits = ranges
+                .into_iter()
+                .map(|range| TextEdit::new(range, params.new_name.clone()))
+                .collect();
+            return Ok(Some(WorkspaceEdit::new(HashMap::from([(
+                uri.clone(),
+                edits,
+            )]))));
+        }
+
+        let Some(name) = self
+            .documents
+            .get(uri)
+            .and_then(|doc| doc.get_symbol_under_cursor(position))
+        else {
+            return Ok(None);
+        };
+        check_renamable(&name)?;
+
+        let mut changes = HashMap::new();
+        for incl_uri in get_reachable_uris(uri, &self.documents) {
+            if let Some(doc) = self.documents.get(&incl_uri) {
+                let edits = doc
+                    .get_references(&name, true, &incl_uri)
+                    .into_iter()
+                    .map(|location| TextEdit::new(location.range, params.new_name.clone()))
+                    .collect::<Vec<_>>();
+                if !edits.is_empty() {
+                    changes.insert(incl_uri, edits);
+                }
+            }
+        }
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        log::info!("document symbol");
+        if let Some(doc) = self.documents.get(&params.text_document.uri) {
+            return Ok(Some(DocumentSymbolResponse::Nested(
+                doc.get_document_symbols(),
+            )));
+        }
+        Ok(None)
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        log::info!("selection range");
+        if let Some(doc) = self.documents.get(&params.text_document.uri) {
+            return Ok(Some(doc.get_selection_ranges(&params.positions)));
+        }
+        Ok(None)
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        log::info!("folding range");
+        if let Some(doc) = self.documents.get(&params.text_document.uri) {
+            return Ok(Some(doc.get_folding_ranges()));
+        }
+        Ok(None)
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        log::info!("document link");
+        if let Some(doc) = self.documents.get(&params.text_document.uri) {
+            return Ok(Some(doc.get_document_links()));
+        }
+        Ok(None)
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        log::info!("workspace symbol: {}", params.query);
+        let mut matches = Vec::new();
+        for entry in self.documents.iter() {
+            matches.extend(
+                entry
+                    .value()
+                    .get_workspace_symbols(&params.query, entry.key()),
+            );
+        }
+        matches.sort_by(|(a, s), (b, t)| a.cmp(b).then(s.name.len().cmp(&t.name.len())));
+        Ok(Some(matches.into_iter().map(|(_, s)| s).collect()))
+    }
+
     async fn semantic_tokens_full(
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
         log::info!("semantic tokens full");
-        if let Some(document) = self.documents.get(&params.text_document.uri) {
+        if let Some(mut document) = self.documents.get_mut(&params.text_document.uri) {
             return document.get_semantic_tokens();
         }
         Ok(None)
     }
 
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        log::info!("semantic tokens full delta");
+        if let Some(mut document) = self.documents.get_mut(&params.text_document.uri) {
+            return document.get_semantic_tokens_delta(&params.previous_result_id);
+        }
+        Ok(None)
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        log::info!("semantic tokens range");
+        if let Some(document) = self.documents.get(&params.text_document.uri) {
+            return document.get_semantic_tokens_range(params.range);
+        }
+        Ok(None)
+    }
+
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> Result<DocumentDiagnosticReportResult> {
+        log::info!("diagnostic");
+        let uri = params.text_document.uri;
+        // include files may be requested before anything loaded them
+        reload_includes(uri.clone(), &self.documents);
+
+        let Some(report) = self.get_diagnostic_report(&uri, params.previous_result_id.as_deref())
+        else {
+            return Err(Error::invalid_params(format!("unknown document {}", uri)));
+        };
+
+        let report = match report {
+            DocumentDiagnosticReportKind::Full(full) => {
+                // the includes can only have changed if the document's report did
+                let related_documents = get_reachable_uris(&uri, &self.documents)
+                    .into_iter()
+                    .skip(1)
+                    .filter_map(|incl| {
+                        let report = self.get_diagnostic_report(&incl, None)?;
+                        Some((incl, report))
+                    })
+                    .collect::<HashMap<_, _>>();
+                DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                    related_documents: Some(related_documents),
+                    full_document_diagnostic_report: full,
+                })
+            }
+            DocumentDiagnosticReportKind::Unchanged(unchanged) => {
+                DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report: unchanged,
+                })
+            }
+        };
+        Ok(DocumentDiagnosticReportResult::Report(report))
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        params: WorkspaceDiagnosticParams,
+    ) -> Result<WorkspaceDiagnosticReportResult> {
+        log::info!("workspace diagnostic");
+        let previous_result_ids = params
+            .previous_result_ids
+            .into_iter()
+            .map(|p| (p.uri, p.value))
+            .collect::<HashMap<_, _>>();
+        let uris = self
+            .documents
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+
+        let items = uris
+            .into_iter()
+            .filter_map(|uri| {
+                let previous_result_id = previous_result_ids.get(&uri).map(String::as_str);
+                let report = self.get_diagnostic_report(&uri, previous_result_id)?;
+                Some(match report {
+                    DocumentDiagnosticReportKind::Full(full) => {
+                        WorkspaceDocumentDiagnosticReport::Full(
+                            WorkspaceFullDocumentDiagnosticReport {
+                                uri,
+                                version: None,
+                                full_document_diagnostic_report: full,
+                            },
+                        )
+                    }
+                    DocumentDiagnosticReportKind::Unchanged(unchanged) => {
+                        WorkspaceDocumentDiagnosticReport::Unchanged(
+                            WorkspaceUnchangedDocumentDiagnosticReport {
+                                uri,
+                                version: None,
+                                unchanged_document_diagnostic_report: unchanged,
+                            },
+                        )
+                    }
+                })
+            })
+            .collect();
+        Ok(WorkspaceDiagnosticReportResult::Report(
+            WorkspaceDiagnosticReport { items },
+        ))
+    }
+
     async fn shutdown(&self) -> Result<()> {
         Ok(())
     }
@@ -272,7 +861,7 @@ fn recheck_problems(
                 Some(Problem::MissingCallee(c, _)) => {
                     // look for callee in labels
                     log::debug!("check problem {}", String::from_utf8(c.clone()).unwrap());
-                    for (label, _) in doc.parser.labels.iter() {
+                    for label in doc.parser.get_label_names() {
                         if label == c {
                             log::debug!("-> match");
                             p.problem = None;
@@ -304,11 +893,132 @@ fn reload_includes(uri: Url, documents: &Arc<DashMap<Url, document::Document>>)
     }
 }
 
+/// finds the definition of the element `name` (lowercase) in the documents `uris`
+fn find_type_hierarchy_item(
+    name: &[u8],
+    uris: &[Url],
+    documents: &Arc<DashMap<Url, document::Document>>,
+) -> Option<TypeHierarchyItem> {
+    uris.iter()
+        .filter_map(|uri| Some((uri, documents.get(uri)?)))
+        .find_map(|(uri, doc)| doc.get_type_hierarchy_item(name, uri))
+}
+
+/// follows the classes of the element `name` (lowercase) up to its builtin root and returns
+/// each ancestor together with the attributes it sets (or, for the builtin, accepts)
+fn get_ancestors(
+    name: &[u8],
+    uri: &Url,
+    documents: &Arc<DashMap<Url, document::Document>>,
+) -> Vec<(String, Vec<String>)> {
+    let reachable_uris = get_reachable_uris(uri, documents);
+    let find_definition = |name: &[u8]| {
+        reachable_uris
+            .iter()
+            .filter_map(|uri| documents.get(uri))
+            .find_map(|doc| doc.get_element_definition(name))
+    };
+
+    let mut ancestors = Vec::new();
+    let mut visited = vec![name.to_vec()];
+    let mut definition = find_definition(name);
+    while let Some((class, _, _)) = definition {
+        // guard against cyclic definitions
+        if visited.contains(&class) {
+            break;
+        }
+        definition = find_definition(&class);
+        let ancestor = String::from_utf8_lossy(&class).to_string();
+        match &definition {
+            Some((_, _, attributes)) => ancestors.push((ancestor, attributes.clone())),
+            None => ancestors
+                .extend(get_builtin_attributes(&class).map(|attributes| (ancestor, attributes))),
+        }
+        visited.push(class);
+    }
+    ancestors
+}
+
+/// a display-only lens showing how often a definition is used
+fn get_reference_lens(range: Range, count: usize) -> CodeLens {
+    let title = match count {
+        0 => "unused".to_string(),
+        1 => "1 reference".to_string(),
+        n => format!("{} references", n),
+    };
+    CodeLens {
+        range,
+        command: Some(Command::new(title, String::new(), None)),
+        data: None,
+    }
+}
+
+/// builtin commands, keywords and builtin constants (`name` is lowercase) belong to madx
+/// the target of a rename has to be a single identifier that isn't reserved itself
+fn check_new_name(new_name: &str) -> Result<()> {
+    let lexer = lexer::Lexer::from_str(new_name);
+    let tokens = lexer.get_tokens();
+    let single_ident = tokens.len() == 1
+        && tokens[0].is_ident()
+        && lexer.get_token_bytes(&tokens[0]).len() == new_name.len();
+    if !single_ident {
+        return Err(Error::invalid_params(format!(
+            "\"{new_name}\" is not a valid name"
+        )));
+    }
+    check_renamable(&new_name.to_ascii_lowercase().into_bytes())
+}
+
+fn check_renamable(name: &[u8]) -> Result<()> {
+    if is_builtin(name) {
+        return Err(Error::invalid_params("builtin commands can't be renamed"));
+    }
+    if is_keyword(name) {
+        return Err(Error::invalid_params("keywords can't be renamed"));
+    }
+    if is_builtin_constant(name) {
+        return Err(Error::invalid_params("builtin constants can't be renamed"));
+    }
+    Ok(())
+}
+
+/// collects `uri` and the uris of all documents reachable from it through (nested) includes.
+/// `uri` itself is always the first entry and every uri appears only once, so include cycles
+/// don't bother us.
+fn get_reachable_uris(uri: &Url, documents: &Arc<DashMap<Url, document::Document>>) -> Vec<Url> {
+    let mut uris = vec![uri.clone()];
+    let mut i = 0;
+    while i < uris.len() {
+        if let Some(doc) = documents.get(&uris[i]) {
+            for incl in doc.parser.includes.iter() {
+                if !uris.contains(incl) {
+                    uris.push(incl.clone());
+                }
+            }
+        }
+        i += 1;
+    }
+    uris
+}
+
 impl Backend {
     async fn resubmit_diagnostics(&self, uri: &Url) {
+        if self.pull_diagnostics.load(Ordering::Relaxed) {
+            return;
+        }
         log::debug!("try resubmit");
 
-        if let Some(doc) = self.documents.get(uri) {
+        if let Some(diagnostics) = self.get_diagnostics(uri) {
+            log::debug!("publishing");
+            self.client
+                .publish_diagnostics(uri.clone(), diagnostics, None)
+                .await;
+        }
+    }
+
+    fn get_diagnostics(&self, uri: &Url) -> Option<Vec<Diagnostic>> {
+        let mut problems = {
+            let doc = self.documents.get(uri)?;
             let mut problems = doc.get_diagnostics();
 
             for p in problems.iter_mut() {
@@ -319,13 +1029,41 @@ impl Backend {
                     _ => {}
                 }
             }
-            recheck_problems(uri, &self.documents, &mut problems);
+            problems
+        };
+        recheck_problems(uri, &self.documents, &mut problems);
+        Some(diagnostics_from_problems(&problems))
+    }
 
-            log::debug!("publishing");
-            self.client
-                .publish_diagnostics(uri.clone(), diagnostics_from_problems(&problems), None)
-                .await;
+    /// the diagnostics of `uri` depend on the document and its includes, so the result id is
+    /// made of their revisions
+    fn get_result_id(&self, uri: &Url) -> String {
+        get_reachable_uris(uri, &self.documents)
+            .iter()
+            .filter_map(|uri| Some(self.documents.get(uri)?.revision().to_string()))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// returns an unchanged report if `previous_result_id` is still valid, otherwise the diagnostics
+    /// are recomputed. `None` if the document is unknown
+    fn get_diagnostic_report(
+        &self,
+        uri: &Url,
+        previous_result_id: Option<&str>,
+    ) -> Option<DocumentDiagnosticReportKind> {
+        let result_id = self.get_result_id(uri);
+        if previous_result_id == Some(result_id.as_str()) {
+            return Some(DocumentDiagnosticReportKind::Unchanged(
+                UnchangedDocumentDiagnosticReport { result_id },
+            ));
         }
+        Some(DocumentDiagnosticReportKind::Full(
+            FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: self.get_diagnostics(uri)?,
+            },
+        ))
     }
 }
 
@@ -379,6 +1117,103 @@ async fn run_server() {
     let (service, socket) = LspService::new(|client| Backend {
         client,
         documents: Arc::new(DashMap::new()),
+        pull_diagnostics: AtomicBool::new(false),
     });
     Server::new(stdin, stdout, socket).serve(service).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a server knowing only the document `text`
+    fn service_with(text: &str) -> (LspService<Backend>, Url) {
+        let uri = Url::from_file_path("/home/job.madx").unwrap();
+        let (service, _) = LspService::new(|client| Backend {
+            client,
+            documents: Arc::new(DashMap::new()),
+            pull_diagnostics: AtomicBool::new(false),
+        });
+        service.inner().documents.insert(
+            uri.clone(),
+            document::Document::new(Some(uri.clone()), text.as_bytes()),
+        );
+        (service, uri)
+    }
+
+    fn position_params(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(uri.clone()),
+            Position::new(line, character),
+        )
+    }
+
+    const TEXT: &str = "qf = 1;\nif (qf > pi) {\n    twiss, sequence=lhcb1;\n}\nb = qf;";
+
+    #[tokio::test]
+    async fn prepare_rename() {
+        let (service, uri) = service_with(TEXT);
+        let backend = service.inner();
+
+        let response = backend.prepare_rename(position_params(&uri, 1, 5)).await;
+        assert_eq!(
+            response.unwrap(),
+            Some(PrepareRenameResponse::Range(Range::new(
+                Position::new(1, 4),
+                Position::new(1, 6)
+            )))
+        );
+        // `if`, `pi` and `twiss`
+        for (line, character) in [(1, 1), (1, 10), (2, 6)] {
+            let response = backend
+                .prepare_rename(position_params(&uri, line, character))
+                .await;
+            assert!(response.is_err(), "{line}:{character}");
+        }
+        // nothing to rename on an operator
+        let response = backend.prepare_rename(position_params(&uri, 1, 7)).await;
+        assert_eq!(response.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rename() {
+        let (service, uri) = service_with(TEXT);
+        let backend = service.inner();
+        let rename = |line, character| RenameParams {
+            text_document_position: position_params(&uri, line, character),
+            new_name: "kqf".to_string(),
+            work_done_progress_params: Default::default(),
+        };
+
+        let edit = backend.rename(rename(4, 5)).await.unwrap().unwrap();
+        let mut ranges: Vec<_> = edit.changes.unwrap()[&uri]
+            .iter()
+            .map(|e| {
+                (
+                    e.range.start.line,
+                    e.range.start.character,
+                    e.new_text.clone(),
+                )
+            })
+            .collect();
+        ranges.sort();
+        assert_eq!(
+            ranges,
+            [
+                (0, 0, "kqf".to_string()),
+                (1, 4, "kqf".to_string()),
+                (4, 4, "kqf".to_string()),
+            ]
+        );
+        for (line, character) in [(1, 1), (1, 10), (2, 6)] {
+            assert!(backend.rename(rename(line, character)).await.is_err());
+        }
+        for new_name in ["", " ", "kqf kqd", "k-qf", "twiss", "if", "pi"] {
+            let params = RenameParams {
+                new_name: new_name.to_string(),
+                ..rename(4, 5)
+            };
+            assert!(backend.rename(params).await.is_err(), "{new_name:?}");
+        }
+    }
+}
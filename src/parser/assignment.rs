@@ -1,10 +1,19 @@
-use crate::lexer::{Token, HasRange, CursorPosition};
+use std::borrow::Cow;
 
-use super::{Expression, Parser, Problem};
+use tower_lsp::lsp_types::SemanticToken;
+
+use crate::lexer::{Token, HasRange, Offset, Shift, CursorPosition};
+use crate::semantic_tokens::{
+    get_modified_range_token, get_range_token, DECLARATION, DEFERRED, READONLY,
+};
+
+use super::{Expression, Operator, Parser, Problem};
 
 #[derive(Debug, PartialEq)]
 pub struct Assignment {
     pub lhs: Box<Expression>,
+    /// `=` or `:=`
+    pub operator: Token,
     pub rhs: Option<Box<Expression>>,
 }
 
@@ -17,17 +26,21 @@ impl Assignment {
                     //parser.go_back();
                     return Some(expr);
                 }
+                let operator = token.clone();
                 parser.advance();
 
-                if let Some(right) = Expression::parse(parser) {
+                let right = Operator::parse(parser).or_else(|| Expression::parse(parser));
+                if let Some(right) = right {
                     return Some(Expression::Assignment(Self {
                         lhs: Box::new(expr),
+                        operator,
                         rhs: Some(Box::new(right)),
                     }));
                 }
                 else {
                     return Some(Expression::Assignment(Self {
                         lhs: Box::new(expr),
+                        operator,
                         rhs: None,
                     }));
                 }
@@ -37,13 +50,77 @@ impl Assignment {
         None
     }
 
-    pub(crate) fn get_label<'a>(&'a self, pos: &CursorPosition, parser: &'a Parser) -> Option<&[u8]> {
+    /// whether the target is declared `const`, as in `real const a = 1;`
+    pub fn is_const(&self, parser: &Parser) -> bool {
+        let tokens = parser.lexer.get_tokens();
+        let start = self.lhs.get_range().0;
+        let index = tokens.partition_point(|t| t.get_range().0 < start);
+        (0..index)
+            .rev()
+            .map_while(|i| match &tokens[i] {
+                Token::Ident(range) => Some(parser.get_element_bytes(range).to_ascii_lowercase()),
+                _ => None,
+            })
+            .take_while(|name| matches!(name.as_slice(), b"const" | b"real" | b"int" | b"shared"))
+            .any(|name| name == b"const")
+    }
+
+    pub fn to_semantic_token(
+        &self,
+        semantic_tokens: &mut Vec<SemanticToken>,
+        pre_line: &mut u32,
+        pre_start: &mut u32,
+        parser: &Parser,
+    ) {
+        if let Expression::TokenExp(Token::Ident(range)) = self.lhs.as_ref() {
+            let mut modifiers = DECLARATION;
+            if let Token::ColonEqual(_) = self.operator {
+                modifiers |= DEFERRED;
+            }
+            if self.is_const(parser) {
+                modifiers |= READONLY;
+            }
+            semantic_tokens.push(get_modified_range_token(
+                range, 9, modifiers, pre_line, pre_start, parser,
+            ));
+        } else {
+            self.lhs
+                .to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
+        }
+        semantic_tokens.push(get_range_token(
+            &self.operator,
+            3,
+            pre_line,
+            pre_start,
+            parser,
+        ));
+        if let Some(rhs) = &self.rhs {
+            rhs.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
+        }
+    }
+
+    pub(crate) fn get_label<'a>(
+        &'a self,
+        pos: &CursorPosition,
+        parser: &'a Parser,
+    ) -> Option<Cow<'a, [u8]>> {
         if let Some(rhs) = &self.rhs {
             return rhs.get_label(pos, parser);
         }
         None
     }
 
+    pub(crate) fn get_references(
+        &self,
+        name: &[u8],
+        parser: &Parser,
+        references: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        if let Some(rhs) = &self.rhs {
+            rhs.get_references(name, parser, references);
+        }
+    }
+
     pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
         if let Some(e) = &self.rhs {
             e.get_problems(problems);
@@ -69,6 +146,14 @@ impl HasRange for Assignment{
     }
 }
 
+impl Shift for Assignment {
+    fn shift(&mut self, offset: &Offset) {
+        self.lhs.shift(offset);
+        self.operator.shift(offset);
+        self.rhs.shift(offset);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,9 +164,74 @@ mod tests {
 
         assert_eq!(parser.get_elements().len(), 1);
         if let Expression::Assignment(assignment) = &parser.get_elements()[0] {
-            assert_eq!(parser.get_element_bytes(assignment), b"a = 1");
-            assert_eq!(parser.get_element_bytes(&*assignment.lhs), b"a");
-            assert_eq!(parser.get_element_bytes(&**assignment.rhs.as_ref().unwrap()), b"1");
+            assert_eq!(*parser.get_element_bytes(assignment), *b"a = 1");
+            assert_eq!(*parser.get_element_bytes(&*assignment.lhs), *b"a");
+            assert_eq!(*parser.get_element_bytes(&**assignment.rhs.as_ref().unwrap()), *b"1");
+        }
+    }
+
+    #[test]
+    fn semantic_token_modifiers() {
+        use crate::semantic_tokens::DEFAULT_LIBRARY;
+
+        let parser = Parser::from_str("real const a = 1;\nb := pi;\nc = 2;\ntwiss, file := c;");
+        let mut tokens = Vec::new();
+        let (mut pre_line, mut pre_start) = (0, 0);
+        for e in parser.get_elements() {
+            e.to_semantic_token(&mut tokens, &mut pre_line, &mut pre_start, &parser);
         }
+
+        let modified: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.token_modifiers_bitset != 0)
+            .map(|t| (t.token_type, t.token_modifiers_bitset))
+            .collect();
+        assert_eq!(
+            modified,
+            [
+                (9, DECLARATION | READONLY),
+                (9, DECLARATION | DEFERRED),
+                (9, READONLY | DEFAULT_LIBRARY),
+                (9, DECLARATION),
+                (4, DEFAULT_LIBRARY),
+                (5, DEFERRED),
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic_token_types() {
+        use crate::semantic_tokens::DEFAULT_LIBRARY;
+
+        let parser = Parser::from_str("real const a = 1;\nb := pi;\nc = 2;\ntwiss, file := c;");
+        let mut tokens = Vec::new();
+        let (mut pre_line, mut pre_start) = (0, 0);
+        for e in parser.get_elements() {
+            e.to_semantic_token(&mut tokens, &mut pre_line, &mut pre_start, &parser);
+        }
+
+        let types: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.token_type, t.token_modifiers_bitset))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (8, 0),
+                (8, 0),
+                (9, DECLARATION | READONLY),
+                (3, 0),
+                (10, 0),
+                (9, DECLARATION | DEFERRED),
+                (3, 0),
+                (9, READONLY | DEFAULT_LIBRARY),
+                (9, DECLARATION),
+                (3, 0),
+                (10, 0),
+                (4, DEFAULT_LIBRARY),
+                (5, DEFERRED),
+                (9, 0),
+            ]
+        );
     }
 }
@@ -1,22 +1,27 @@
-use std::{collections::HashMap, fmt::Display};
+use std::borrow::Cow;
 
 use once_cell::sync::Lazy;
-use tower_lsp::lsp_types::{CompletionItem, SemanticToken};
+use tower_lsp::lsp_types::{
+    CompletionItem, DocumentSymbol, FoldingRange, SemanticToken, SymbolKind,
+};
 
 use crate::{
-    lexer::{CursorPosition, HasRange, Token},
-    semantic_tokens::get_range_token,
+    document_symbols::get_document_symbol,
+    folding_ranges::get_folding_range,
+    lexer::{CursorPosition, HasRange, Offset, Shift, Token},
+    semantic_tokens::{get_modified_range_token, get_range_token, push_token, DECLARATION},
 };
 
 use super::{
-    insert_generic_builder, Assignment, Environment, If, Label, Macro, MadExec, MadGeneric,
-    MadGenericBuilder, Parser, Problem, GENERIC_BUILTINS,
+    Array, Assignment, Call, Environment, Group, If, Label, Macro, MadExec, MadGeneric, OpKind,
+    Operator, Parser, Problem, While,
 };
 #[derive(Debug, PartialEq)]
 pub enum Expression {
     Label(Label),
     Macro(Macro),
     If(If),
+    While(While),
     Assignment(Assignment),
     String((CursorPosition, CursorPosition)),
     Comment((CursorPosition, CursorPosition)),
@@ -25,6 +30,9 @@ pub enum Expression {
     MadEnvironment(Environment),
     Exit(Exit),
     Operator(Operator),
+    Group(Group),
+    Call(Call),
+    Array(Array),
     Exec(MadExec),
     Noop(CursorPosition),
     TokenExp(Token), // debug, todo: remove
@@ -36,13 +44,17 @@ impl HasRange for Expression {
             Expression::String(r) => *r,
             Expression::Comment(r) => *r,
             Expression::If(i) => i.get_range(),
+            Expression::While(w) => w.get_range(),
             Expression::Macro(m) => m.get_range(),
-            Expression::Label(_) => todo!(),
+            Expression::Label(l) => l.get_range(),
             Expression::Symbol(_) => todo!(),
             Expression::MadGeneric(g) => g.get_range(),
             Expression::MadEnvironment(e) => e.get_range(),
             Expression::Assignment(a) => a.get_range(),
-            Expression::Operator(_) => todo!(),
+            Expression::Operator(op) => op.get_range(),
+            Expression::Group(g) => g.get_range(),
+            Expression::Call(c) => c.get_range(),
+            Expression::Array(a) => a.get_range(),
             Expression::TokenExp(token) => token.get_range(),
             Expression::Exit(exit) => (exit.start, exit.end),
             Expression::Exec(exec) => exec.get_range(),
@@ -51,6 +63,31 @@ impl HasRange for Expression {
     }
 }
 
+impl Shift for Expression {
+    fn shift(&mut self, offset: &Offset) {
+        match self {
+            Expression::Label(l) => l.shift(offset),
+            Expression::Macro(m) => m.shift(offset),
+            Expression::If(i) => i.shift(offset),
+            Expression::While(w) => w.shift(offset),
+            Expression::Assignment(a) => a.shift(offset),
+            Expression::String(r) => r.shift(offset),
+            Expression::Comment(r) => r.shift(offset),
+            Expression::Symbol(_) => {}
+            Expression::MadGeneric(g) => g.shift(offset),
+            Expression::MadEnvironment(e) => e.shift(offset),
+            Expression::Exit(exit) => exit.shift(offset),
+            Expression::Operator(op) => op.shift(offset),
+            Expression::Group(g) => g.shift(offset),
+            Expression::Call(c) => c.shift(offset),
+            Expression::Array(a) => a.shift(offset),
+            Expression::Exec(exec) => exec.shift(offset),
+            Expression::Noop(pos) => pos.shift(offset),
+            Expression::TokenExp(token) => token.shift(offset),
+        }
+    }
+}
+
 impl Expression {
     pub fn parse(parser: &mut Parser) -> Option<Self> {
         if let Some(m) = Macro::parse(parser) {
@@ -74,6 +111,9 @@ impl Expression {
         if let Some(if_object) = If::parse(parser) {
             return Some(Expression::If(if_object));
         }
+        if let Some(while_object) = While::parse(parser) {
+            return Some(Expression::While(while_object));
+        }
 
         if let Some(exit) = Exit::parse(parser) {
             return Some(Expression::Exit(exit));
@@ -93,6 +133,7 @@ impl Expression {
             Expression::MadEnvironment(e) => e.accept(visitor),
             Expression::Exec(e) => e.accept(visitor),
             Expression::If(i) => i.accept(visitor),
+            Expression::While(w) => w.accept(visitor),
             Expression::Label(l) => l.accept(visitor),
             _ => {},
         }
@@ -110,49 +151,60 @@ impl Expression {
             Expression::MadEnvironment(e) => e.get_problems(problems),
             Expression::Exit(_) => {}
             Expression::Operator(_) => {}
+            Expression::Group(_) => {}
+            Expression::Call(_) => {}
+            Expression::Array(_) => {}
             Expression::Exec(e) => e.get_problems(problems),
             Expression::TokenExp(_) => {}
-            Expression::If(_) => {}
+            Expression::If(i) => i.get_problems(problems),
+            Expression::While(w) => w.get_problems(problems),
             Expression::Noop(cursor_position) => {}
         }
     }
 
-    fn parse_string(parser: &mut Parser) -> Option<Self> {
-        if let Some(Token::DoubleQuotes(p)) = parser.peek_token().cloned() {
+    pub(crate) fn parse_string(parser: &mut Parser) -> Option<Self> {
+        if let Some(Token::String(range)) = parser.peek_token().cloned() {
             parser.advance();
-            while let Some(token) = parser.peek_token().cloned() {
-                parser.advance();
-                if let Token::DoubleQuotes(p_end) = token {
-                    return Some(Self::String((p, p_end)));
-                }
-            }
-        }
-        if let Some(Token::Quotes(p)) = parser.peek_token().cloned() {
-            parser.advance();
-            while let Some(token) = parser.peek_token().cloned() {
-                parser.advance();
-                if let Token::Quotes(p_end) = token {
-                    return Some(Self::String((p, p_end)));
-                }
-            }
+            return Some(Self::String(range));
         }
         None
     }
 
+    /// returns the range of the name this expression defines, i.e. the name of a label or a macro
+    /// or the left hand side of an assignment
+    pub fn get_name_range(&self) -> Option<(CursorPosition, CursorPosition)> {
+        match self {
+            Expression::Label(l) => Some(l.name.get_range()),
+            Expression::Macro(m) => Some(m.name.get_range()),
+            Expression::Assignment(a) => Some(a.lhs.get_range()),
+            _ => None,
+        }
+    }
+
     /// returns the label of the element under cursor, this is to find the definition and,
     /// possibly, jump to it
-    pub fn get_label<'a>(&'a self, pos: &CursorPosition, parser: &'a Parser) -> Option<&[u8]> {
+    pub fn get_label<'a>(
+        &'a self,
+        pos: &CursorPosition,
+        parser: &'a Parser,
+    ) -> Option<Cow<'a, [u8]>> {
         match self {
-            Expression::Label(_) => None,
+            Expression::Label(l) => l.get_label(pos, parser),
             Expression::Macro(_) => None,
             Expression::Assignment(a) => a.get_label(pos, parser),
             Expression::String(_) => None,
             Expression::Comment(_) => None,
-            Expression::Symbol(s) => Some(s.as_bytes()),
+            Expression::Symbol(s) => Some(Cow::Borrowed(s.as_bytes())),
             Expression::MadGeneric(m) => m.get_label(pos, parser),
             Expression::MadEnvironment(m) => m.get_label(pos, parser),
             Expression::Exit(_) => None,
-            Expression::Operator(_) => None,
+            Expression::Operator(_)
+            | Expression::Group(_)
+            | Expression::Call(_)
+            | Expression::Array(_) => self
+                .get_operands()
+                .into_iter()
+                .find_map(|e| e.get_label(pos, parser)),
             Expression::Exec(s) => s.get_label(pos, parser),
             Expression::TokenExp(t) => {
                 let range = t.get_range();
@@ -163,10 +215,119 @@ impl Expression {
                 }
             }
             Expression::If(_) => None,
+            Expression::While(_) => None,
             Expression::Noop(cursor_position) => None,
         }
     }
 
+    /// returns the outline entry of this expression, commands and plain tokens have none
+    pub fn get_document_symbol(&self, parser: &Parser) -> Option<DocumentSymbol> {
+        match self {
+            Expression::Label(l) => Some(get_document_symbol(
+                parser.get_element_str(&l.name),
+                Some(parser.get_element_str(&l.command.name)),
+                SymbolKind::OBJECT,
+                l,
+                &l.name,
+                vec![],
+                parser,
+            )),
+            Expression::Macro(m) => Some(m.get_document_symbol(parser)),
+            Expression::If(i) => Some(i.get_document_symbol(parser)),
+            Expression::While(w) => Some(w.get_document_symbol(parser)),
+            Expression::Assignment(a) => Some(get_document_symbol(
+                parser.get_element_str(&*a.lhs),
+                a.rhs.as_ref().map(|rhs| parser.get_element_str(&**rhs)),
+                SymbolKind::VARIABLE,
+                a,
+                &*a.lhs,
+                vec![],
+                parser,
+            )),
+            Expression::MadEnvironment(e) => Some(e.get_document_symbol(parser)),
+            _ => None,
+        }
+    }
+
+    /// collects the ranges of this expression and its children that contain `pos`, from the
+    /// outermost to the innermost
+    pub fn get_selection_ranges(
+        &self,
+        pos: CursorPosition,
+        ranges: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        let range = self.get_range();
+        if pos < range.0 || range.1 < pos {
+            return;
+        }
+        ranges.push(range);
+        match self {
+            Expression::Label(l) => l.command.get_selection_ranges(pos, ranges),
+            Expression::Macro(m) => get_body_selection_ranges(&m.body, pos, ranges),
+            Expression::If(i) => {
+                get_body_selection_ranges(&i.condition, pos, ranges);
+                get_body_selection_ranges(&i.body, pos, ranges);
+                for branch in i.branches.iter() {
+                    let range = branch.get_range();
+                    if range.0 <= pos && pos <= range.1 {
+                        ranges.push(range);
+                        get_body_selection_ranges(&branch.condition, pos, ranges);
+                        get_body_selection_ranges(&branch.body, pos, ranges);
+                    }
+                }
+            }
+            Expression::While(w) => {
+                get_body_selection_ranges(&w.condition, pos, ranges);
+                get_body_selection_ranges(&w.body, pos, ranges);
+            }
+            Expression::Assignment(a) => {
+                a.lhs.get_selection_ranges(pos, ranges);
+                if let Some(rhs) = &a.rhs {
+                    rhs.get_selection_ranges(pos, ranges);
+                }
+            }
+            Expression::MadGeneric(g) => g.get_selection_ranges(pos, ranges),
+            Expression::MadEnvironment(e) => get_body_selection_ranges(&e.expressions, pos, ranges),
+            Expression::Operator(_)
+            | Expression::Group(_)
+            | Expression::Call(_)
+            | Expression::Array(_) => {
+                for e in self.get_operands() {
+                    e.get_selection_ranges(pos, ranges);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// collects the foldable blocks (macros, if-blocks and environments) of this expression
+    pub fn get_folding_ranges(&self, ranges: &mut Vec<FoldingRange>, parser: &Parser) {
+        let (start, end, body) = match self {
+            Expression::Macro(m) => (m.name.get_range().0, m.get_range().1, &m.body),
+            Expression::If(i) => {
+                ranges.extend(get_folding_range(i.parenopen, i.get_body_end(), None, parser));
+                for branch in i.branches.iter() {
+                    let start = branch.parens.map_or(branch.keyword.get_range().0, |p| p.0);
+                    ranges.extend(get_folding_range(start, branch.get_range().1, None, parser));
+                }
+                for e in i.bodies() {
+                    e.get_folding_ranges(ranges, parser);
+                }
+                return;
+            }
+            Expression::While(w) => (w.parenopen, w.get_range().1, &w.body),
+            Expression::MadEnvironment(e) => {
+                let (start, end) = e.get_range();
+                (start, end, &e.expressions)
+            }
+            _ => return,
+        };
+        ranges.extend(get_folding_range(start, end, None, parser));
+        for e in body {
+            e.get_folding_ranges(ranges, parser);
+        }
+    }
+
     pub fn get_completion(&self, pos: &CursorPosition, items: &mut Vec<CompletionItem>) {
         match self {
             Expression::Label(_) => {}
@@ -180,8 +341,12 @@ impl Expression {
             Expression::Exit(_) => {}
             Expression::Exec(_) => {}
             Expression::Operator(_) => {}
+            Expression::Group(_) => {}
+            Expression::Call(_) => {}
+            Expression::Array(_) => {}
             Expression::TokenExp(_) => {}
-            Expression::If(_) => {}
+            Expression::If(i) => i.get_completion(pos, items),
+            Expression::While(w) => w.get_completion(pos, items),
             Expression::Noop(cursor_position) => {}
         }
     }
@@ -194,19 +359,44 @@ impl Expression {
     ) {
         match self {
             Self::String(range) => {
-                semantic_tokens.push(get_range_token(range, 0, pre_line, pre_start, parser))
+                semantic_tokens.push(get_range_token(range, 1, pre_line, pre_start, parser))
+            }
+            Self::TokenExp(token) => {
+                push_token(token, semantic_tokens, pre_line, pre_start, parser)
+            }
+            Self::If(if_exp) => {
+                if_exp.to_semantic_token(semantic_tokens, pre_line, pre_start, parser)
+            }
+            Self::While(while_exp) => {
+                while_exp.to_semantic_token(semantic_tokens, pre_line, pre_start, parser)
+            }
+            Self::Exec(exec) => {
+                exec.to_semantic_token(semantic_tokens, pre_line, pre_start, parser)
+            }
+            Self::Operator(op) => {
+                op.to_semantic_token(semantic_tokens, pre_line, pre_start, parser)
             }
-            Self::TokenExp(Token::Comment(range)) => {
-                semantic_tokens.push(get_range_token(range, 2, pre_line, pre_start, parser))
+            Self::Call(call) => {
+                call.to_semantic_token(semantic_tokens, pre_line, pre_start, parser)
             }
-            Self::TokenExp(Token::MultilineComment(lines)) => {
-                for range in lines.iter() {
-                    semantic_tokens.push(get_range_token(range, 2, pre_line, pre_start, parser));
+            Self::Group(_) | Self::Array(_) => {
+                for e in self.get_operands() {
+                    e.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
                 }
             }
+            Self::Assignment(assignment) => {
+                assignment.to_semantic_token(semantic_tokens, pre_line, pre_start, parser)
+            }
             Self::Macro(m) => m.to_semantic_token(semantic_tokens, pre_line, pre_start, parser),
             Self::Label(label) => {
-                semantic_tokens.push(get_range_token(&label.name, 6, pre_line, pre_start, parser));
+                semantic_tokens.push(get_modified_range_token(
+                    &label.name,
+                    6,
+                    DECLARATION,
+                    pre_line,
+                    pre_start,
+                    parser,
+                ));
                 label
                     .command
                     .to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
@@ -220,8 +410,9 @@ impl Expression {
             Self::Exit(exit) => {
                 semantic_tokens.push(get_range_token(exit, 0, pre_line, pre_start, parser));
 
-                for lines in parser.lexer.lines()[exit.start.line() + 1..].windows(2) {
-                    let length = lines[1] - lines[0];
+                let lexer = &parser.lexer;
+                for line in lexer.line(&exit.start) + 1..lexer.line_count().saturating_sub(1) {
+                    let length = lexer.line_start(line + 1) - lexer.line_start(line);
                     semantic_tokens.push(SemanticToken {
                         delta_line: 1,
                         delta_start: 0,
@@ -235,6 +426,109 @@ impl Expression {
         }
     }
 
+    /// collects the ranges of all usages of the symbol `name` (lowercase) in this expression,
+    /// i.e. exec callees, parameter values and right hand sides of assignments
+    pub(crate) fn get_references(
+        &self,
+        name: &[u8],
+        parser: &Parser,
+        references: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        match self {
+            Expression::Label(l) => l.command.get_references(name, parser, references),
+            Expression::Macro(m) => m.get_references(name, parser, references),
+            Expression::If(i) => i.get_references(name, parser, references),
+            Expression::While(w) => w.get_references(name, parser, references),
+            Expression::Assignment(a) => a.get_references(name, parser, references),
+            Expression::MadGeneric(g) => g.get_references(name, parser, references),
+            Expression::MadEnvironment(e) => e.get_references(name, parser, references),
+            Expression::Exec(e) => e.get_references(name, parser, references),
+            Expression::TokenExp(Token::Ident(range)) if parser.lexer.compare_range(range, name) => {
+                references.push(*range);
+            }
+            Expression::Operator(_)
+            | Expression::Group(_)
+            | Expression::Call(_)
+            | Expression::Array(_) => {
+                for e in self.get_operands() {
+                    e.get_references(name, parser, references);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// the sub-expressions of an arithmetic expression that can refer to a symbol, i.e. all but
+    /// function names and the attribute of `element->attribute`
+    fn get_operands(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Operator(op) if op.kind == OpKind::Member => {
+                op.left.iter().map(AsRef::as_ref).collect()
+            }
+            Expression::Operator(op) => op
+                .left
+                .iter()
+                .chain(std::iter::once(&op.right))
+                .map(AsRef::as_ref)
+                .collect(),
+            Expression::Group(g) => vec![g.inner.as_ref()],
+            Expression::Call(c) => c.args.iter().collect(),
+            Expression::Array(a) => a.items.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// collects the ranges of all definitions of the symbol `name` (lowercase) in this expression,
+    /// including the ones nested in macro, if and environment bodies
+    pub(crate) fn get_declarations(
+        &self,
+        name: &[u8],
+        parser: &Parser,
+        declarations: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        if let Some(range) = self.get_name_range() {
+            if parser.lexer.compare_range(&range, name) {
+                declarations.push(range);
+            }
+        }
+        if let Expression::Macro(m) = self {
+            if m.has_arg(name, parser) {
+                return;
+            }
+        }
+        for e in self.get_bodies() {
+            e.get_declarations(name, parser, declarations);
+        }
+    }
+
+    /// collects the definitions (macros, labels and variables) in this expression, including
+    /// the ones nested in macro, if and environment bodies. Each comes with the name range of the
+    /// innermost definition containing it.
+    pub(crate) fn get_definitions<'a>(
+        &'a self,
+        container: Option<(CursorPosition, CursorPosition)>,
+        definitions: &mut Vec<(&'a Expression, Option<(CursorPosition, CursorPosition)>)>,
+    ) {
+        let name = self.get_name_range();
+        if name.is_some() {
+            definitions.push((self, container));
+        }
+        for e in self.get_bodies() {
+            e.get_definitions(name.or(container), definitions);
+        }
+    }
+
+    /// the expressions in the bodies of macros, ifs, whiles and environments
+    pub(crate) fn get_bodies(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Macro(m) => m.body.iter().collect(),
+            Expression::If(i) => i.bodies().collect(),
+            Expression::While(w) => w.body.iter().collect(),
+            Expression::MadEnvironment(env) => env.expressions.iter().collect(),
+            _ => vec![],
+        }
+    }
+
     pub(crate) fn get_highlights(
         &self,
         pos: &CursorPosition,
@@ -251,19 +545,39 @@ impl Expression {
             Expression::MadEnvironment(_) => vec![],
             Expression::Exit(_) => vec![],
             Expression::Operator(_) => vec![],
+            Expression::Group(_) => vec![],
+            Expression::Call(_) => vec![],
+            Expression::Array(_) => vec![],
             Expression::Exec(_) => vec![],
             Expression::TokenExp(_) => vec![],
             Expression::If(_) => vec![],
+            Expression::While(_) => vec![],
             Expression::Noop(_) => vec![],
         }
     }
 }
 
+/// the selection ranges of a block: the whole body, then the expression containing `pos`
+fn get_body_selection_ranges(
+    body: &[Expression],
+    pos: CursorPosition,
+    ranges: &mut Vec<(CursorPosition, CursorPosition)>,
+) {
+    if let (Some(first), Some(last)) = (body.first(), body.last()) {
+        let range = (first.get_range().0, last.get_range().1);
+        if range.0 <= pos && pos <= range.1 {
+            ranges.push(range);
+        }
+    }
+    for e in body {
+        e.get_selection_ranges(pos, ranges);
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Exit {
     start: CursorPosition,
     end: CursorPosition,
-    length: usize,
 }
 
 impl HasRange for Exit {
@@ -272,6 +586,13 @@ impl HasRange for Exit {
     }
 }
 
+impl Shift for Exit {
+    fn shift(&mut self, offset: &Offset) {
+        self.start.shift(offset);
+        self.end.shift(offset);
+    }
+}
+
 impl Exit {
     pub fn parse(parser: &mut Parser) -> Option<Self> {
         if let Some(Token::Ident(name)) = parser.peek_token() {
@@ -284,25 +605,9 @@ impl Exit {
                 return Some(Self {
                     start: name.0,
                     end: name.1,
-                    length: parser.lexer.len() - name.1.absolute(),
                 });
             }
         }
         None
     }
 }
-
-#[derive(Debug, PartialEq, Eq)]
-pub enum OpKind {
-    Add,
-    Sub,
-    Mul,
-    Div,
-}
-
-#[derive(Debug, PartialEq)]
-pub struct Operator {
-    kind: OpKind,
-    left: Box<Expression>,
-    right: Box<Expression>,
-}
@@ -1,4 +1,6 @@
-use crate::lexer::{HasRange, Token};
+use std::borrow::Cow;
+
+use crate::lexer::{CursorPosition, HasRange, Offset, Shift, Token};
 
 use super::{MadGeneric, Parser};
 
@@ -26,6 +28,13 @@ impl Label {
                         command: mad_generic,
                     });
                 }
+                // or as an element derived from another one
+                if let Some(mad_generic) = MadGeneric::parse_derived(parser) {
+                    return Some(Self {
+                        name,
+                        command: mad_generic,
+                    });
+                }
             }
 
             parser.go_back();
@@ -33,6 +42,27 @@ impl Label {
         None
     }
 
+    /// the label under the cursor can be the name of this element, the class it's derived from
+    /// or a symbol used in its attributes
+    pub(crate) fn get_label<'a>(
+        &'a self,
+        pos: &CursorPosition,
+        parser: &'a Parser,
+    ) -> Option<Cow<'a, [u8]>> {
+        for token in [&self.name, &self.command.name] {
+            let range = token.get_range();
+            if &range.0 < pos && pos < &range.1 {
+                return Some(parser.get_element_bytes(&range));
+            }
+        }
+        self.command.get_label(pos, parser)
+    }
+
+    /// the (lowercase) class this element is derived from
+    pub fn get_class(&self, parser: &Parser) -> Vec<u8> {
+        parser.get_element_bytes(&self.command.name).to_ascii_lowercase()
+    }
+
     pub(crate) fn accept<V: crate::visitor::Visitor>(&self, visitor: &mut V) {
         visitor.visit_label(self);
     }
@@ -44,6 +74,13 @@ impl HasRange for Label {
     }
 }
 
+impl Shift for Label {
+    fn shift(&mut self, offset: &Offset) {
+        self.name.shift(offset);
+        self.command.shift(offset);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::parser::Expression;
@@ -63,4 +100,23 @@ mod tests {
             assert_eq!(parser.labels.keys().collect::<Vec<_>>(), vec![b"label"]);
         }
     }
+
+    #[test]
+    fn parse_derived_label() {
+        let parser = Parser::from_str("mq: quadrupole, l=3.1;\nmq.a1: MQ, k1:=kq1, tilt;");
+
+        if let Expression::Label(label) = &parser.get_elements()[2] {
+            assert_eq!(parser.get_element_str(&label.name), "mq.a1");
+            assert!(label.command.is_derived());
+            assert_eq!(label.get_class(&parser), b"mq");
+            assert!(label.command.args.iter().all(|arg| arg.valid));
+            assert_eq!(label.command.args.len(), 2);
+        } else {
+            panic!("expected label, got {:?}", parser.get_elements());
+        }
+
+        // environments and macros are no classes
+        let parser = Parser::from_str("m: macro = { twiss; };");
+        assert!(!matches!(&parser.get_elements()[0], Expression::Label(_)));
+    }
 }
@@ -1,19 +1,20 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 use once_cell::sync::Lazy;
-use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind};
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, DocumentSymbol, SymbolKind};
 
 use crate::{
+    document_symbols::get_document_symbol,
     error::UTF8_PARSER_MSG,
-    lexer::{CursorPosition, HasRange, Token},
-    semantic_tokens::get_range_token,
+    lexer::{CursorPosition, HasRange, Offset, Shift, Token},
+    semantic_tokens::{get_modified_range_token, DEFAULT_LIBRARY},
 };
 
 use super::{
     insert_generic_builder, Expression, MadGenericBuilder, MadParam, MatchParam, Parser, Problem,
 };
 
-pub const GENERIC_ENVS: Lazy<HashMap<&'static [u8], EnvironmentBuilder>> = Lazy::new(|| {
+pub static GENERIC_ENVS: Lazy<HashMap<&'static [u8], EnvironmentBuilder>> = Lazy::new(|| {
     let mut envs = HashMap::new();
 
     insert_generic_env(
@@ -242,7 +243,9 @@ pub struct Environment {
     match_start: &'static [u8],
     args: Vec<MadParam>,
     start: Token,
-    end: Token,
+    /// the closing command, or the last expression if the environment is not closed. `None` if
+    /// it is empty and not closed.
+    end: Option<Token>,
     pub expressions: Vec<Expression>,
 }
 
@@ -264,7 +267,8 @@ impl Environment {
     }
 
     pub fn get_completion(&self, pos: &CursorPosition, items: &mut Vec<CompletionItem>) {
-        if &self.start.get_range().0 < pos && &self.end.get_range().1 > pos {
+        let range = self.get_range();
+        if &range.0 < pos && &range.1 > pos {
             for expr in self.expressions.iter() {
                 expr.get_completion(pos, items);
             }
@@ -288,9 +292,10 @@ impl Environment {
         pre_start: &mut u32,
         parser: &Parser,
     ) {
-        semantic_tokens.push(get_range_token(
+        semantic_tokens.push(get_modified_range_token(
             &self.start.get_range(),
             7,
+            DEFAULT_LIBRARY,
             pre_line,
             pre_start,
             parser,
@@ -301,20 +306,53 @@ impl Environment {
             expr.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
         }
 
-        semantic_tokens.push(get_range_token(
-            &self.end.get_range(),
-            7,
-            pre_line,
-            pre_start,
+        if let Some(end) = &self.end {
+            semantic_tokens.push(get_modified_range_token(
+                &end.get_range(),
+                7,
+                DEFAULT_LIBRARY,
+                pre_line,
+                pre_start,
+                parser,
+            ));
+        }
+    }
+
+    /// the environment is listed as a namespace, containing its commands as methods
+    pub fn get_document_symbol(&self, parser: &Parser) -> DocumentSymbol {
+        let children = self
+            .expressions
+            .iter()
+            .filter_map(|e| match e {
+                Expression::MadGeneric(g) => Some(get_document_symbol(
+                    parser.get_element_str(&g.name),
+                    None,
+                    SymbolKind::METHOD,
+                    g,
+                    &g.name,
+                    vec![],
+                    parser,
+                )),
+                _ => e.get_document_symbol(parser),
+            })
+            .collect();
+
+        get_document_symbol(
+            parser.get_element_str(&self.start),
+            None,
+            SymbolKind::NAMESPACE,
+            self,
+            &self.start,
+            children,
             parser,
-        ));
+        )
     }
 
     pub(crate) fn get_label<'a>(
         &'a self,
         pos: &CursorPosition,
         parser: &'a Parser,
-    ) -> Option<&[u8]> {
+    ) -> Option<Cow<'a, [u8]>> {
         let range = self.start.get_range();
         if &range.0 < pos && pos < &range.1 {
             return Some(parser.get_element_bytes(&range));
@@ -322,6 +360,20 @@ impl Environment {
         None
     }
 
+    pub(crate) fn get_references(
+        &self,
+        name: &[u8],
+        parser: &Parser,
+        references: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        for arg in self.args.iter() {
+            arg.get_references(name, parser, references);
+        }
+        for e in self.expressions.iter() {
+            e.get_references(name, parser, references);
+        }
+    }
+
     pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
         log::debug!(
             "forwarding problems for {} expressions",
@@ -341,7 +393,17 @@ impl Environment {
 
 impl HasRange for Environment {
     fn get_range(&self) -> (crate::lexer::CursorPosition, crate::lexer::CursorPosition) {
-        (self.start.get_range().0, self.end.get_range().1)
+        let end = self.end.as_ref().unwrap_or(&self.start);
+        (self.start.get_range().0, end.get_range().1)
+    }
+}
+
+impl Shift for Environment {
+    fn shift(&mut self, offset: &Offset) {
+        self.args.shift(offset);
+        self.start.shift(offset);
+        self.end.shift(offset);
+        self.expressions.shift(offset);
     }
 }
 
@@ -384,7 +446,7 @@ impl EnvironmentBuilder {
                 if let Some(expr) = Expression::parse(parser) {
                     if let Expression::TokenExp(end) = &expr {
                         if parser.lexer.compare_range(end, self.match_end) {
-                            env.end = end.clone();
+                            env.end = Some(end.clone());
                             return Some(env);
                         }
                     }
@@ -393,9 +455,7 @@ impl EnvironmentBuilder {
                 }
                 break;
             }
-            if let Some(last) = env.expressions.last() {
-                env.end = Token::Ident(last.get_range());
-            }
+            env.end = env.expressions.last().map(|last| Token::Ident(last.get_range()));
             return Some(env);
         }
         None
@@ -413,10 +473,10 @@ mod tests {
         let seqedit = &parser.get_elements()[0];
 
         if let Expression::MadEnvironment(env) = seqedit {
-            assert_eq!(parser.get_element_bytes(env), b"seqedit; flatten; endedit");
-            assert_eq!(parser.get_element_bytes(&env.start), b"seqedit");
-            assert_eq!(parser.get_element_bytes(&env.expressions[1]), b"flatten");
-            assert_eq!(parser.get_element_bytes(&env.end), b"endedit");
+            assert_eq!(*parser.get_element_bytes(env), *b"seqedit; flatten; endedit");
+            assert_eq!(*parser.get_element_bytes(&env.start), *b"seqedit");
+            assert_eq!(*parser.get_element_bytes(&env.expressions[1]), *b"flatten");
+            assert_eq!(*parser.get_element_bytes(env.end.as_ref().unwrap()), *b"endedit");
         } else {
             assert!(false, "should be an env");
         }
@@ -435,7 +495,7 @@ mod tests {
             );
             assert_eq!(parser.get_element_str(&env.start), "seqedit");
             assert_eq!(parser.get_element_str(&env.expressions[1]), "flatten");
-            assert_eq!(parser.get_element_str(&env.end), ";");
+            assert_eq!(parser.get_element_str(env.end.as_ref().unwrap()), ";");
 
             let mut st = Vec::new();
             let mut pre_line = 0;
@@ -1,19 +1,32 @@
-use crate::lexer::{CursorPosition, HasRange, Token};
+use std::borrow::Cow;
 
-use super::{Expression, Macro, Problem};
+use tower_lsp::lsp_types::SemanticToken;
+
+use crate::lexer::{CursorPosition, HasRange, Offset, Shift, Token};
+use crate::semantic_tokens::{get_range_token, push_token};
+
+use super::Problem;
 
 #[derive(Debug, Eq, PartialEq, Clone, Default)]
 pub struct MadExec {
     name: Token,
     callee: Token,
-    parenopen: CursorPosition,
+    parenopen: Option<CursorPosition>,
+    /// all tokens between the parentheses, including the separating commas
     args: Vec<Token>,
-    parenclose: CursorPosition,
+    /// `None` if the arguments are not closed
+    parenclose: Option<CursorPosition>,
 }
 
 impl MadExec {
     pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
-        problems.push(Problem::MissingCallee(vec![], self.callee.get_range()));
+        // a call without a callee is reported at the `exec`
+        let range = if self.callee.is_ident() {
+            self.callee.get_range()
+        } else {
+            self.name.get_range()
+        };
+        problems.push(Problem::MissingCallee(vec![], range));
     }
 
     pub(crate) fn parse(parser: &mut super::Parser) -> Option<MadExec> {
@@ -36,10 +49,10 @@ impl MadExec {
                     return Some(exec);
                 }
 
-                if let Some((popen, args, pclose)) = Macro::read_parenthesis(parser) {
-                    exec.parenopen = popen;
-                    exec.parenclose = pclose;
-                    exec.args = args;
+                if let Some(Token::ParentOpen(popen)) = parser.peek_token() {
+                    exec.parenopen = Some(*popen);
+                    parser.advance();
+                    exec.read_arguments(parser);
                 }
 
                 return Some(exec);
@@ -48,11 +61,70 @@ impl MadExec {
         None
     }
 
+    pub fn to_semantic_token(
+        &self,
+        semantic_tokens: &mut Vec<SemanticToken>,
+        pre_line: &mut u32,
+        pre_start: &mut u32,
+        parser: &super::Parser,
+    ) {
+        semantic_tokens.push(get_range_token(&self.name, 8, pre_line, pre_start, parser));
+        if let Token::Ident(callee) = &self.callee {
+            semantic_tokens.push(get_range_token(callee, 4, pre_line, pre_start, parser));
+        }
+        for token in self.args.iter() {
+            push_token(token, semantic_tokens, pre_line, pre_start, parser);
+        }
+    }
+
+    /// reads the tokens up to the closing parenthesis. An unclosed call ends at the next `;`,
+    /// which is left for the caller.
+    fn read_arguments(&mut self, parser: &mut super::Parser) {
+        let mut depth = 0;
+        while let Some(token) = parser.peek_token().cloned() {
+            match token {
+                Token::SemiColon(_) => return,
+                Token::ParentClose(pclose) if depth == 0 => {
+                    self.parenclose = Some(pclose);
+                    parser.advance();
+                    return;
+                }
+                Token::ParentClose(_) => depth -= 1,
+                Token::ParentOpen(_) => depth += 1,
+                _ => {}
+            }
+            self.args.push(token);
+            parser.advance();
+        }
+    }
+
+    /// the ranges of the comma separated arguments of the call
+    pub fn get_arguments(&self) -> Vec<(CursorPosition, CursorPosition)> {
+        let mut arguments = Vec::new();
+        let mut current: Option<(CursorPosition, CursorPosition)> = None;
+        let mut depth = 0;
+        for token in self.args.iter() {
+            match token {
+                Token::ParentOpen(_) => depth += 1,
+                Token::ParentClose(_) => depth -= 1,
+                Token::Komma(_) if depth == 0 => {
+                    arguments.extend(current.take());
+                    continue;
+                }
+                _ => {}
+            }
+            let range = token.get_range();
+            current = Some(current.map_or(range, |(start, _)| (start, range.1)));
+        }
+        arguments.extend(current);
+        arguments
+    }
+
     pub(crate) fn get_label<'a>(
         &'a self,
         pos: &CursorPosition,
         parser: &'a super::Parser,
-    ) -> Option<&'a [u8]> {
+    ) -> Option<Cow<'a, [u8]>> {
         let range = self.callee.get_range();
         if &range.0 < pos && pos < &range.1 {
             Some(parser.get_element_bytes(&range))
@@ -61,6 +133,20 @@ impl MadExec {
         }
     }
 
+    /// the callee and the arguments of the call can refer to `name`
+    pub(crate) fn get_references(
+        &self,
+        name: &[u8],
+        parser: &super::Parser,
+        references: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        for token in std::iter::once(&self.callee).chain(self.args.iter()) {
+            if token.is_ident() && parser.lexer.compare_range(token, name) {
+                references.push(token.get_range());
+            }
+        }
+    }
+
     pub fn get_callee(&self) -> (CursorPosition, CursorPosition) {
         self.callee.get_range()
     }
@@ -72,6 +158,24 @@ impl MadExec {
 
 impl HasRange for MadExec {
     fn get_range(&self) -> (crate::lexer::CursorPosition, crate::lexer::CursorPosition) {
-        (self.name.get_range().0, self.parenclose + 1)
+        // an incomplete call ends with the last part that is there
+        let end = match (self.parenclose, self.args.last(), self.parenopen) {
+            (Some(parenclose), _, _) => parenclose + 1,
+            (None, Some(arg), _) => arg.get_range().1,
+            (None, None, Some(parenopen)) => parenopen + 1,
+            (None, None, None) if self.callee.is_ident() => self.callee.get_range().1,
+            (None, None, None) => self.name.get_range().1,
+        };
+        (self.name.get_range().0, end)
+    }
+}
+
+impl Shift for MadExec {
+    fn shift(&mut self, offset: &Offset) {
+        self.name.shift(offset);
+        self.callee.shift(offset);
+        self.parenopen.shift(offset);
+        self.args.shift(offset);
+        self.parenclose.shift(offset);
     }
 }
@@ -1,17 +1,17 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap};
 
 use once_cell::sync::Lazy;
-use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind};
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, SignatureInformation};
 
-use crate::{lexer::{Token, CursorPosition, HasRange}, semantic_tokens::{get_range_token}, error::UTF8_PARSER_MSG};
+use crate::{lexer::{Token, CursorPosition, HasRange, Offset, Shift}, semantic_tokens::{get_modified_range_token, DEFAULT_LIBRARY, DEFERRED}, error::UTF8_PARSER_MSG, signature_help::get_signature_information};
 
-use super::{Expression, Parser, Problem};
+use super::{is_builtin, Expression, Operator, Parser, Problem};
 
 pub type MatchParam = (Vec<u8>, Vec<Vec<u8>>);
 
 // ---- const map of generic madx commands ---------------------------------------------------------
 
-pub const GENERIC_BUILTINS: Lazy<HashMap<&'static [u8], MadGenericBuilder>> = Lazy::new(|| {
+pub static GENERIC_BUILTINS: Lazy<HashMap<&'static [u8], MadGenericBuilder>> = Lazy::new(|| {
     let mut builtins = HashMap::new();
     insert_generic_builder(&mut builtins, b"option", &["echo", "warn", "verbose", "debug", "echomacro",
                            "trace", "verify", "tell", "reset", "no_fatal_stop", "keep_exp_move", "rbarc", "thin_foc", "bborbit", "sympl",
@@ -30,6 +30,7 @@ pub const GENERIC_BUILTINS: Lazy<HashMap<&'static [u8], MadGenericBuilder>> = La
     insert_generic_builder(&mut builtins, b"create", &["table", "column"]);
     insert_generic_builder(&mut builtins, b"delete", &["table", "sequence"]);
     insert_generic_builder(&mut builtins, b"readmytable", &["table", "file"]);
+    insert_generic_builder(&mut builtins, b"readtable", &["file", "table"]);
     insert_generic_builder(&mut builtins, b"twiss", &["sequence", "line", "range",
                            "deltap", "chrom", "centre", "tolerance", "file", "table", "notable",
                            "rmatrix", "sectormap", "sectortable", "sectorfile", "sectorpure",
@@ -120,6 +121,8 @@ pub struct MadParam{
     pub valid: bool,
     pub sign: Option<Token>,
     pub attribute: Token,
+    /// `=` or `:=`, flags don't have one
+    pub assign: Option<Token>,
     pub value: Option<Box<Expression>>,
 }
 
@@ -141,6 +144,36 @@ impl MadGeneric {
         None
     }
 
+    /// parses a command whose name is not a builtin but another element, i.e. the class of a
+    /// derived element like `mq.a1: mq, k1:=kq1;`. All attributes are accepted and
+    /// `match_name` is left empty.
+    pub fn parse_derived(parser: &mut Parser) -> Option<Self> {
+        let Some(Token::Ident(name)) = parser.peek_token().cloned() else { return None; };
+        if is_builtin(&parser.get_element_bytes(&name).to_ascii_lowercase()) {
+            return None;
+        }
+        match parser.get_token(parser.get_position() + 1) {
+            Some(Token::Komma(_)) | Some(Token::SemiColon(_)) => {}
+            _ => return None,
+        }
+        parser.advance();
+
+        let mut args = MadParam::parse_params(parser, &Vec::new());
+        for arg in args.iter_mut() {
+            arg.valid = true;
+        }
+        Some(Self {
+            match_name: b"",
+            name: Token::Ident(name),
+            args,
+        })
+    }
+
+    /// whether this is the class of a derived element, see [`MadGeneric::parse_derived`]
+    pub fn is_derived(&self) -> bool {
+        self.match_name.is_empty()
+    }
+
     pub fn get_completion(&self, pos: &CursorPosition, items: &mut Vec<CompletionItem>) {
         let range = self.get_range();
         if &range.0 < pos && pos < &range.1 {
@@ -164,19 +197,51 @@ impl MadGeneric {
         }
     }
     pub fn to_semantic_token(&self, semantic_tokens: &mut Vec<tower_lsp::lsp_types::SemanticToken>, pre_line: &mut u32, pre_start: &mut u32, parser: &Parser) {
-        if let Token::Ident(range) = self.name {semantic_tokens.push(get_range_token(&range, 4, pre_line, pre_start, parser));}
+        if let Token::Ident(range) = self.name {
+            let modifiers = if self.is_derived() { 0 } else { DEFAULT_LIBRARY };
+            semantic_tokens.push(get_modified_range_token(&range, 4, modifiers, pre_line, pre_start, parser));
+        }
 
         MadParam::to_semantic_token(&self.args, semantic_tokens, pre_line, pre_start, parser);
 
     }
 
-    pub(crate) fn get_label<'a>(&'a self, pos: &CursorPosition, parser: &'a Parser) -> Option<&[u8]> {
+    pub(crate) fn get_label<'a>(
+        &'a self,
+        pos: &CursorPosition,
+        parser: &'a Parser,
+    ) -> Option<Cow<'a, [u8]>> {
         for p in self.args.iter() {
             if let Some(label) = p.get_label(pos, parser) { return Some(label); }
         }
         None
     }
 
+    pub(crate) fn get_references(&self, name: &[u8], parser: &Parser, references: &mut Vec<(CursorPosition, CursorPosition)>) {
+        // the class of a derived element refers to its parent
+        if self.is_derived() && parser.lexer.compare_range(&self.name, name) {
+            references.push(self.name.get_range());
+        }
+        for arg in self.args.iter() {
+            arg.get_references(name, parser, references);
+        }
+    }
+
+    /// the selection ranges of the command and of the attribute (`attribute = value`) containing
+    /// `pos`
+    pub(crate) fn get_selection_ranges(&self, pos: CursorPosition, ranges: &mut Vec<(CursorPosition, CursorPosition)>) {
+        ranges.push(self.get_range());
+        for arg in self.args.iter() {
+            let range = arg.get_range();
+            if range.0 <= pos && pos <= range.1 {
+                ranges.push(range);
+                if let Some(value) = &arg.value {
+                    value.get_selection_ranges(pos, ranges);
+                }
+            }
+        }
+    }
+
     pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
         for arg in self.args.iter() {
             if !arg.valid {
@@ -206,6 +271,22 @@ impl HasRange for MadGeneric {
     }
 }
 
+impl Shift for MadGeneric {
+    fn shift(&mut self, offset: &Offset) {
+        self.name.shift(offset);
+        self.args.shift(offset);
+    }
+}
+
+impl Shift for MadParam {
+    fn shift(&mut self, offset: &Offset) {
+        self.sign.shift(offset);
+        self.attribute.shift(offset);
+        self.assign.shift(offset);
+        self.value.shift(offset);
+    }
+}
+
 // ---- MadParam -----------------------------------------------------------------------------------
 impl MadParam {
     pub fn parse(parser: &mut Parser) -> Option<Self> {
@@ -214,6 +295,7 @@ impl MadParam {
                 valid: false,
                 sign: None,
                 attribute: Default::default(),
+                assign: None,
                 value: None,
             };
             if let Token::Operator(_) = token {
@@ -226,28 +308,18 @@ impl MadParam {
                     parser.advance();
                 }
             }
-            if let Some(Token::Equal(_)) = parser.peek_token() {
+            if let Some(assign) = parser.peek_token().filter(|t| t.is_assignment()) {
+                param.assign = Some(assign.clone());
                 parser.advance();
-                
-                let last_pos = parser.get_position();
+
                 // todo: missing test for syntax error
-                if let Some(expr) = Expression::parse(parser) {
-                    param.value = match expr {
-                        Expression::MadGeneric(_) => {
-                            parser.set_position(last_pos);
-                            let token = parser.next_token().unwrap(); // we know that here's a
-                                                                      // valid token
-                            Some(Box::new(Expression::TokenExp(token.clone())))
-                        },
-                        Expression::MadEnvironment(_) => {
-                            parser.set_position(last_pos);
-                            let token = parser.next_token().unwrap(); // we know that here's a
-                                                                      // valid token
-                            Some(Box::new(Expression::TokenExp(token.clone())))
-                        },
-                        _ => Some(Box::new(expr))
-                    };
-                }
+                param.value = Operator::parse(parser)
+                    .or_else(|| {
+                        let token = parser.peek_token().cloned()?;
+                        parser.advance();
+                        Some(Expression::TokenExp(token))
+                    })
+                    .map(Box::new);
             }
             if !param.attribute.is_eof() {
                 return Some(param);
@@ -260,7 +332,8 @@ impl MadParam {
         for arg in args.iter() {
             if !arg.valid {continue;}
             let range = arg.attribute.get_range();
-            semantic_tokens.push(get_range_token(&arg.attribute, 5, pre_line, pre_start, parser));
+            let modifiers = if let Some(Token::ColonEqual(_)) = arg.assign { DEFERRED } else { 0 };
+            semantic_tokens.push(get_modified_range_token(&arg.attribute, 5, modifiers, pre_line, pre_start, parser));
             if let Some(value) = &arg.value {
                 value.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
             }
@@ -312,9 +385,19 @@ impl MadParam {
         args
     }
 
-    fn get_label<'a>(&'a self, pos: &CursorPosition, parser: &'a Parser) -> Option<&[u8]> {
+    fn get_label<'a>(
+        &'a self,
+        pos: &CursorPosition,
+        parser: &'a Parser,
+    ) -> Option<Cow<'a, [u8]>> {
         self.value.as_ref()?.get_label(pos, parser)
     }
+
+    pub(crate) fn get_references(&self, name: &[u8], parser: &Parser, references: &mut Vec<(CursorPosition, CursorPosition)>) {
+        if let Some(value) = &self.value {
+            value.get_references(name, parser, references);
+        }
+    }
 }
 
 // ---- MadGenericBuilder --------------------------------------------------------------------------
@@ -339,6 +422,23 @@ impl MadGenericBuilder {
         None
     }
 
+    /// the signature `name, attribute1, attribute2, ...` shown while typing the command,
+    /// `attribute` is the (lowercase) attribute that is currently being typed
+    pub fn get_signature(&self, attribute: Option<&[u8]>) -> SignatureInformation {
+        get_signature_information(
+            format!("{}, ", String::from_utf8_lossy(self.match_name)),
+            self.match_params.iter().map(|(param, flags)| {
+                let documentation = (!flags.is_empty()).then(|| {
+                    let flags = flags.iter().map(|f| String::from_utf8_lossy(f)).collect::<Vec<_>>();
+                    format!("one of: {}", flags.join(", "))
+                });
+                (String::from_utf8_lossy(param).to_string(), documentation)
+            }),
+            "",
+            attribute.and_then(|a| self.match_params.iter().position(|(p, _)| p == a)).map(|i| i as u32),
+        )
+    }
+
     //pub fn has_attribute(&self, name: &[u8]) -> bool {
     //    self.match_params.iter().any(|p| p == name)
     //}
@@ -412,5 +512,20 @@ mod tests {
             assert!(false, "this should be recognized as incomplete CALL");
         }
     }
+
+    #[test]
+    pub fn deferred_value() {
+        let parser = Parser::from_str("twiss, sequence=lhcb1, deltap:=dp;");
+
+        if let Expression::MadGeneric(g) = &parser.get_elements()[0] {
+            assert_eq!(g.args.len(), 2);
+            assert_eq!(parser.get_element_str(&g.args[1].attribute), "deltap");
+            let value = g.args[1].value.as_ref().expect("deferred value should be parsed");
+            assert_eq!(parser.get_element_str(&**value), "dp");
+        }
+        else {
+            assert!(false, "this should be recognized as TWISS");
+        }
+    }
 }
 
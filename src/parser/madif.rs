@@ -1,15 +1,49 @@
+use tower_lsp::lsp_types::{CompletionItem, DocumentSymbol, SemanticToken, SymbolKind};
+
 use crate::{
-    lexer::{CursorPosition, HasRange, Token},
-    parser::{Assignment, Expression, Parser},
+    document_symbols::get_document_symbol,
+    lexer::{CursorPosition, HasRange, Offset, Shift, Token},
+    parser::{Assignment, Expression, Operator, Parser, Problem},
+    semantic_tokens::get_range_token,
 };
 
 #[derive(Debug, PartialEq, Default)]
 pub struct If {
+    pub keyword: Token,
     pub parenopen: CursorPosition,
-    pub parenclose: CursorPosition,
+    /// the end of the closing parenthesis, `None` if the condition is not closed
+    pub parenclose: Option<CursorPosition>,
     pub condition: Vec<Expression>, // should be only one
     pub body: Vec<Expression>,
-    pub end: CursorPosition,
+    /// the end of the closing brace, `None` if the body is not closed
+    pub end: Option<CursorPosition>,
+    /// the `elseif` and `else` branches following the body
+    pub branches: Vec<Branch>,
+}
+
+/// an `elseif (...) { }`, `else if (...) { }` or `else { }` following an if
+#[derive(Debug, PartialEq, Default)]
+pub struct Branch {
+    /// `elseif` or `else`
+    pub keyword: Token,
+    /// the `if` of `else if`
+    pub if_keyword: Option<Token>,
+    /// the parentheses around the condition, a plain `else` has none. The closing one is
+    /// missing if the condition is not closed.
+    pub parens: Option<(CursorPosition, Option<CursorPosition>)>,
+    pub condition: Vec<Expression>,
+    pub body: Vec<Expression>,
+    pub end: Option<CursorPosition>,
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct While {
+    pub keyword: Token,
+    pub parenopen: CursorPosition,
+    pub parenclose: Option<CursorPosition>,
+    pub condition: Vec<Expression>,
+    pub body: Vec<Expression>,
+    pub end: Option<CursorPosition>,
 }
 
 impl If {
@@ -27,62 +61,521 @@ impl If {
     }
 
     pub fn parse_inner(parser: &mut Parser) -> Option<Self> {
-        if let Some(Token::Ident(if_keyword)) = parser.peek_token() {
-            if parser.lexer.compare_range(if_keyword, b"if") {
-                parser.advance();
-            } else {
-                return None;
+        let mut if_object = If {
+            keyword: parse_keyword(parser, b"if")?,
+            ..Default::default()
+        };
+        (if_object.parenopen, if_object.parenclose) =
+            parse_condition(parser, &mut if_object.condition)?;
+        if_object.end = parse_body(parser, &mut if_object.body)?;
+
+        while let Some(branch) = Branch::parse(parser) {
+            let is_else = branch.parens.is_none();
+            if_object.branches.push(branch);
+            if is_else {
+                break;
             }
-        } else {
-            return None;
         }
 
-        let mut if_object = If::default();
+        Some(if_object)
+    }
 
-        if let Some(Token::ParentOpen(pos)) = parser.next_token() {
-            if_object.parenopen = pos.clone();
-        } else {
-            return None;
+    /// the expressions of all conditions and bodies, in source order
+    pub fn expressions(&self) -> impl Iterator<Item = &Expression> {
+        let branches = self
+            .branches
+            .iter()
+            .flat_map(|b| b.condition.iter().chain(b.body.iter()));
+        self.condition
+            .iter()
+            .chain(self.body.iter())
+            .chain(branches)
+    }
+
+    /// the expressions of the body and of the branch bodies
+    pub fn bodies(&self) -> impl Iterator<Item = &Expression> {
+        let branches = self.branches.iter().flat_map(|b| b.body.iter());
+        self.body.iter().chain(branches)
+    }
+
+    /// the range of the condition including its parentheses
+    pub fn get_condition_range(&self) -> (CursorPosition, CursorPosition) {
+        condition_range(self.parenopen, self.parenclose, &self.condition)
+    }
+
+    /// the end of the body, without the branches
+    pub fn get_body_end(&self) -> CursorPosition {
+        body_end(self.end, &self.body, self.get_condition_range().1)
+    }
+
+    pub fn to_semantic_token(
+        &self,
+        semantic_tokens: &mut Vec<SemanticToken>,
+        pre_line: &mut u32,
+        pre_start: &mut u32,
+        parser: &Parser,
+    ) {
+        semantic_tokens.push(get_range_token(
+            &self.keyword,
+            8,
+            pre_line,
+            pre_start,
+            parser,
+        ));
+        for expr in self.condition.iter().chain(self.body.iter()) {
+            expr.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
         }
-        while let Some(expr) = Assignment::parse(parser) {
-            if let Expression::TokenExp(Token::ParentClose(end)) = expr {
-                if_object.parenclose = end + 1;
-                break;
+        for branch in self.branches.iter() {
+            for keyword in std::iter::once(&branch.keyword).chain(branch.if_keyword.iter()) {
+                semantic_tokens.push(get_range_token(keyword, 8, pre_line, pre_start, parser));
+            }
+            for expr in branch.condition.iter().chain(branch.body.iter()) {
+                expr.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
             }
-            if_object.condition.push(expr);
         }
+    }
 
-        if let Some(Token::BraceOpen(_)) = parser.peek_token() {
-            parser.advance();
-        } else {
-            return None;
+    pub fn get_document_symbol(&self, parser: &Parser) -> DocumentSymbol {
+        let condition = self.get_condition_range();
+        get_document_symbol(
+            format!("if {}", parser.get_element_str(&condition)),
+            None,
+            SymbolKind::NAMESPACE,
+            self,
+            &condition,
+            self.bodies()
+                .filter_map(|e| e.get_document_symbol(parser))
+                .collect(),
+            parser,
+        )
+    }
+
+    pub fn get_completion(&self, pos: &CursorPosition, items: &mut Vec<CompletionItem>) {
+        for e in self.bodies() {
+            e.get_completion(pos, items);
         }
+    }
 
-        while let Some(expr) = Assignment::parse(parser) {
-            if let Expression::TokenExp(Token::BraceClose(end)) = expr {
-                if_object.end = end + 1;
-                break;
-            }
-            if_object.body.push(expr);
+    pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
+        for e in self.expressions() {
+            e.get_problems(problems);
         }
+    }
 
-        Some(if_object)
+    pub(crate) fn get_references(
+        &self,
+        name: &[u8],
+        parser: &Parser,
+        references: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        for expr in self.expressions() {
+            expr.get_references(name, parser, references);
+        }
     }
 
     pub(crate) fn accept<V: crate::visitor::Visitor>(&self, visitor: &mut V) {
         visitor.visit_if(self);
 
-        for expr in self.condition.iter() {
+        for expr in self.expressions() {
             expr.accept(visitor);
         }
-        for expr in self.body.iter() {
+    }
+}
+
+impl Branch {
+    fn parse(parser: &mut Parser) -> Option<Self> {
+        let before = parser.get_position();
+
+        let maybe_branch = Self::parse_inner(parser);
+
+        if maybe_branch.is_some() {
+            return maybe_branch;
+        }
+
+        parser.set_position(before);
+        None
+    }
+
+    fn parse_inner(parser: &mut Parser) -> Option<Self> {
+        let mut branch = Branch::default();
+        let has_condition = if let Some(keyword) = parse_keyword(parser, b"elseif") {
+            branch.keyword = keyword;
+            true
+        } else {
+            branch.keyword = parse_keyword(parser, b"else")?;
+            branch.if_keyword = parse_keyword(parser, b"if");
+            branch.if_keyword.is_some()
+        };
+        if has_condition {
+            branch.parens = Some(parse_condition(parser, &mut branch.condition)?);
+        }
+        branch.end = parse_body(parser, &mut branch.body)?;
+
+        Some(branch)
+    }
+
+    /// the range of the condition including its parentheses, a plain `else` has none
+    pub fn get_condition_range(&self) -> Option<(CursorPosition, CursorPosition)> {
+        self.parens
+            .map(|(open, close)| condition_range(open, close, &self.condition))
+    }
+}
+
+impl While {
+    pub fn parse(parser: &mut Parser) -> Option<Self> {
+        let before = parser.get_position();
+
+        let maybe_while = Self::parse_inner(parser);
+
+        if maybe_while.is_some() {
+            return maybe_while;
+        }
+
+        parser.set_position(before);
+        None
+    }
+
+    fn parse_inner(parser: &mut Parser) -> Option<Self> {
+        let mut while_object = While {
+            keyword: parse_keyword(parser, b"while")?,
+            ..Default::default()
+        };
+        (while_object.parenopen, while_object.parenclose) =
+            parse_condition(parser, &mut while_object.condition)?;
+        while_object.end = parse_body(parser, &mut while_object.body)?;
+
+        Some(while_object)
+    }
+
+    /// the range of the condition including its parentheses
+    pub fn get_condition_range(&self) -> (CursorPosition, CursorPosition) {
+        condition_range(self.parenopen, self.parenclose, &self.condition)
+    }
+
+    pub fn to_semantic_token(
+        &self,
+        semantic_tokens: &mut Vec<SemanticToken>,
+        pre_line: &mut u32,
+        pre_start: &mut u32,
+        parser: &Parser,
+    ) {
+        semantic_tokens.push(get_range_token(
+            &self.keyword,
+            8,
+            pre_line,
+            pre_start,
+            parser,
+        ));
+        for expr in self.condition.iter().chain(self.body.iter()) {
+            expr.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
+        }
+    }
+
+    pub fn get_document_symbol(&self, parser: &Parser) -> DocumentSymbol {
+        let condition = self.get_condition_range();
+        get_document_symbol(
+            format!("while {}", parser.get_element_str(&condition)),
+            None,
+            SymbolKind::NAMESPACE,
+            self,
+            &condition,
+            self.body
+                .iter()
+                .filter_map(|e| e.get_document_symbol(parser))
+                .collect(),
+            parser,
+        )
+    }
+
+    pub fn get_completion(&self, pos: &CursorPosition, items: &mut Vec<CompletionItem>) {
+        for e in self.body.iter() {
+            e.get_completion(pos, items);
+        }
+    }
+
+    pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
+        for e in self.condition.iter().chain(self.body.iter()) {
+            e.get_problems(problems);
+        }
+    }
+
+    pub(crate) fn get_references(
+        &self,
+        name: &[u8],
+        parser: &Parser,
+        references: &mut Vec<(CursorPosition, CursorPosition)>,
+    ) {
+        for expr in self.condition.iter().chain(self.body.iter()) {
+            expr.get_references(name, parser, references);
+        }
+    }
+
+    pub(crate) fn accept<V: crate::visitor::Visitor>(&self, visitor: &mut V) {
+        visitor.visit_while(self);
+
+        for expr in self.condition.iter().chain(self.body.iter()) {
             expr.accept(visitor);
         }
     }
 }
 
+/// consumes the keyword `name` (lowercase) if it is next
+fn parse_keyword(parser: &mut Parser, name: &[u8]) -> Option<Token> {
+    if let Some(Token::Ident(range)) = parser.peek_token() {
+        if parser.lexer.compare_range(range, name) {
+            let keyword = Token::Ident(*range);
+            parser.advance();
+            return Some(keyword);
+        }
+    }
+    None
+}
+
+/// parses `(condition)` into `condition` and returns the opening parenthesis and the end of the
+/// closing one, which is missing if the condition is not closed
+fn parse_condition(
+    parser: &mut Parser,
+    condition: &mut Vec<Expression>,
+) -> Option<(CursorPosition, Option<CursorPosition>)> {
+    let parenopen = if let Some(Token::ParentOpen(pos)) = parser.next_token() {
+        *pos
+    } else {
+        return None;
+    };
+    condition.extend(Operator::parse(parser));
+    while let Some(expr) = Assignment::parse(parser) {
+        if let Expression::TokenExp(Token::ParentClose(end)) = expr {
+            return Some((parenopen, Some(end + 1)));
+        }
+        condition.push(expr);
+    }
+    Some((parenopen, None))
+}
+
+/// parses `{ body }` into `body` and returns the end of the closing brace, which is missing if
+/// the body is not closed
+fn parse_body(parser: &mut Parser, body: &mut Vec<Expression>) -> Option<Option<CursorPosition>> {
+    if let Some(Token::BraceOpen(_)) = parser.peek_token() {
+        parser.advance();
+    } else {
+        return None;
+    }
+
+    while let Some(expr) = Assignment::parse(parser) {
+        if let Expression::TokenExp(Token::BraceClose(end)) = expr {
+            return Some(Some(end + 1));
+        }
+        body.push(expr);
+    }
+    Some(None)
+}
+
+/// an unclosed condition ends with its last expression
+fn condition_range(
+    parenopen: CursorPosition,
+    parenclose: Option<CursorPosition>,
+    condition: &[Expression],
+) -> (CursorPosition, CursorPosition) {
+    let end = parenclose
+        .or_else(|| condition.last().map(|e| e.get_range().1))
+        .unwrap_or(parenopen + 1);
+    (parenopen, end)
+}
+
+/// an unclosed body ends with its last expression, or with what precedes it if it is empty
+fn body_end(
+    end: Option<CursorPosition>,
+    body: &[Expression],
+    before: CursorPosition,
+) -> CursorPosition {
+    end.or_else(|| body.last().map(|e| e.get_range().1))
+        .unwrap_or(before)
+}
+
 impl HasRange for If {
     fn get_range(&self) -> (CursorPosition, CursorPosition) {
-        (self.end, self.end)
+        let end = match self.branches.last() {
+            Some(branch) => branch.get_range().1,
+            None => self.get_body_end(),
+        };
+        (self.keyword.get_range().0, end)
+    }
+}
+
+impl HasRange for Branch {
+    fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        let keyword = self.keyword.get_range();
+        let before = self.get_condition_range().map_or(keyword.1, |c| c.1);
+        (keyword.0, body_end(self.end, &self.body, before))
+    }
+}
+
+impl HasRange for While {
+    fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        let before = self.get_condition_range().1;
+        (self.keyword.get_range().0, body_end(self.end, &self.body, before))
+    }
+}
+
+impl Shift for If {
+    fn shift(&mut self, offset: &Offset) {
+        self.keyword.shift(offset);
+        self.parenopen.shift(offset);
+        self.parenclose.shift(offset);
+        self.condition.shift(offset);
+        self.body.shift(offset);
+        self.end.shift(offset);
+        self.branches.shift(offset);
+    }
+}
+
+impl Shift for Branch {
+    fn shift(&mut self, offset: &Offset) {
+        self.keyword.shift(offset);
+        self.if_keyword.shift(offset);
+        self.parens.shift(offset);
+        self.condition.shift(offset);
+        self.body.shift(offset);
+        self.end.shift(offset);
+    }
+}
+
+impl Shift for While {
+    fn shift(&mut self, offset: &Offset) {
+        self.keyword.shift(offset);
+        self.parenopen.shift(offset);
+        self.parenclose.shift(offset);
+        self.condition.shift(offset);
+        self.body.shift(offset);
+        self.end.shift(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic_tokens::DECLARATION;
+
+    #[test]
+    fn semantic_tokens() {
+        let parser = Parser::from_str("if (a > 1) {\n    exec, m(a, 2);\n} else {\n    b = 3;\n}");
+        let mut tokens = Vec::new();
+        let (mut pre_line, mut pre_start) = (0, 0);
+        for e in parser.get_elements() {
+            e.to_semantic_token(&mut tokens, &mut pre_line, &mut pre_start, &parser);
+        }
+
+        let types: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.token_type, t.token_modifiers_bitset))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (8, 0),
+                (9, 0),
+                (3, 0),
+                (10, 0),
+                (8, 0),
+                (4, 0),
+                (9, 0),
+                (10, 0),
+                (8, 0),
+                (9, DECLARATION),
+                (3, 0),
+                (10, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn branches() {
+        let text = "if (a > 1) {\n    b = 1;\n} else if (a < 0) {\n    b = 2;\n} elseif (a == 0) {\n    b = 3;\n} else {\n    b = 4;\n}";
+        let parser = Parser::from_str(text);
+        assert_eq!(parser.get_elements().len(), 1);
+        let Expression::If(if_object) = &parser.get_elements()[0] else {
+            panic!("expected an if")
+        };
+
+        assert_eq!(parser.get_element_str(if_object), text);
+        let branches: Vec<_> = if_object
+            .branches
+            .iter()
+            .map(|b| {
+                (
+                    b.if_keyword.is_some(),
+                    b.get_condition_range().map(|c| parser.get_element_str(&c)),
+                    parser.get_element_str(b),
+                )
+            })
+            .collect();
+        assert_eq!(
+            branches,
+            [
+                (
+                    true,
+                    Some("(a < 0)".to_string()),
+                    "else if (a < 0) {\n    b = 2;\n}".to_string()
+                ),
+                (
+                    false,
+                    Some("(a == 0)".to_string()),
+                    "elseif (a == 0) {\n    b = 3;\n}".to_string()
+                ),
+                (false, None, "else {\n    b = 4;\n}".to_string()),
+            ]
+        );
+        assert_eq!(if_object.bodies().count(), 8);
+
+        let mut tokens = Vec::new();
+        let (mut pre_line, mut pre_start) = (0, 0);
+        if_object.to_semantic_token(&mut tokens, &mut pre_line, &mut pre_start, &parser);
+        let keywords = tokens.iter().filter(|t| t.token_type == 8).count();
+        assert_eq!(keywords, 5);
+    }
+
+    #[test]
+    fn while_loop() {
+        let text = "while (i <= n) {\n    i = i + 1;\n}";
+        let parser = Parser::from_str(&format!("{}\nx = 1;", text));
+        let Expression::While(while_object) = &parser.get_elements()[0] else {
+            panic!("expected a while")
+        };
+
+        assert_eq!(parser.get_element_str(while_object), text);
+        assert_eq!(
+            parser.get_element_str(&while_object.get_condition_range()),
+            "(i <= n)"
+        );
+        assert_eq!(
+            while_object.get_document_symbol(&parser).name,
+            "while (i <= n)"
+        );
+
+        let mut references = Vec::new();
+        while_object.get_references(b"i", &parser, &mut references);
+        assert_eq!(references.len(), 2);
+
+        let mut tokens = Vec::new();
+        let (mut pre_line, mut pre_start) = (0, 0);
+        while_object.to_semantic_token(&mut tokens, &mut pre_line, &mut pre_start, &parser);
+        let types: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.token_type, t.token_modifiers_bitset))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (8, 0),
+                (9, 0),
+                (3, 0),
+                (9, 0),
+                (9, DECLARATION),
+                (3, 0),
+                (9, 0),
+                (3, 0),
+                (10, 0),
+            ]
+        );
     }
 }
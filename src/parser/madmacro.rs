@@ -1,6 +1,6 @@
-use tower_lsp::lsp_types::{CompletionItem, SemanticToken};
+use tower_lsp::lsp_types::{CompletionItem, DocumentSymbol, SemanticToken, SignatureInformation, SymbolKind};
 
-use crate::{lexer::{Token, CursorPosition, HasRange}, semantic_tokens::get_range_token};
+use crate::{lexer::{Token, CursorPosition, HasRange, Offset, Shift}, semantic_tokens::{get_range_token, get_modified_range_token, set_token_type, DECLARATION}, document_symbols::get_document_symbol, signature_help::get_signature_information};
 
 use super::{Expression, Parser, Assignment, Problem};
 
@@ -12,7 +12,8 @@ pub struct Macro {
     pub parenclose: CursorPosition,
     pub macro_pos: Token,
     pub body: Vec<Expression>,
-    pub end: CursorPosition,
+    /// the end of the closing brace, `None` if the body is not closed
+    pub end: Option<CursorPosition>,
 }
 
 
@@ -84,7 +85,7 @@ impl Macro {
 
             while let Some(expr) = Assignment::parse(parser) {
                 if let Expression::TokenExp(Token::BraceClose(end)) = expr {
-                    m.end = end + 1;
+                    m.end = Some(end + 1);
                     break;
                 }
                 m.body.push(expr);
@@ -139,104 +140,99 @@ impl Macro {
 
     pub fn to_semantic_token(&self, semantic_tokens: &mut Vec<SemanticToken>, pre_line: &mut u32, pre_start: &mut u32, parser: &Parser) {
 
-        semantic_tokens.push(get_range_token(&self.name, 4, pre_line, pre_start, parser));
+        semantic_tokens.push(get_modified_range_token(&self.name, 4, DECLARATION, pre_line, pre_start, parser));
+        for arg in self.args.iter() {
+            semantic_tokens.push(get_modified_range_token(arg, 11, DECLARATION, pre_line, pre_start, parser));
+        }
         semantic_tokens.push(get_range_token(&self.macro_pos, 8, pre_line, pre_start, parser));
 
+        // the body is tokenized as usual first, then the arguments in it are marked
+        let (body_line, body_start) = (*pre_line, *pre_start);
+        let mut body_tokens = Vec::new();
         for e in self.body.iter() {
-            e.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
+            e.to_semantic_token(&mut body_tokens, pre_line, pre_start, parser);
         }
+        let ranges: Vec<_> = self.get_arg_occurrences(parser).into_iter().map(|(_, range)| range).collect();
+        let (tokens, last_line, last_start) =
+            set_token_type(&body_tokens, (body_line, body_start), &ranges, 11, parser);
+        semantic_tokens.extend(tokens);
+        *pre_line = last_line;
+        *pre_start = last_start;
+    }
 
-        // this doesn't work
-        /*
-        let mut arg_tokens = Vec::new();
-        let start_inner = self.macro_pos.get_range().1;
-        if let Ok(inner_text) = String::from_utf8(parser.get_element_bytes(&(start_inner, self.end))
-            .to_ascii_lowercase()) {
-
-            let mut pline = *pre_line;
-            let mut pstart = *pre_start;
-
-            for arg in self.args.iter()
-                .filter_map(|arg| String::from_utf8(parser.get_element_bytes(arg).to_ascii_lowercase()).ok()) {
-                    log::debug!("look for arg {}", arg);
-                    arg_tokens.extend(inner_text.match_indices(&arg).map(|(idx, _)| {
-                        let mut pos0 = start_inner;
-                        let mut pos1 = start_inner;
-                        parser.lexer.advance_cursor(&mut pos0, idx);
-                        parser.lexer.advance_cursor(&mut pos1, idx + arg.len());
-                        get_range_token(&(pos0, pos1), 9, &mut pline, &mut pstart, parser)
-                    }));
+    /// the identifiers in the body naming one of the arguments, together with the index of the
+    /// argument
+    pub fn get_arg_occurrences(&self, parser: &Parser) -> Vec<(usize, (CursorPosition, CursorPosition))> {
+        let args: Vec<_> = self.args.iter()
+            .map(|arg| parser.get_element_bytes(arg).to_ascii_lowercase())
+            .collect();
+        let start = self.macro_pos.get_range().1;
+        let end = self.get_range().1;
+
+        let tokens = parser.lexer.get_tokens();
+        let first = tokens.partition_point(|t| t.get_range().0 < start);
+        tokens.iter().skip(first)
+            .take_while(|t| t.get_range().0 < end)
+            .filter_map(|t| match t {
+                Token::Ident(range) => {
+                    let name = parser.get_element_bytes(range).to_ascii_lowercase();
+                    args.iter().position(|arg| *arg == name).map(|index| (index, *range))
                 }
-        }
+                _ => None,
+            })
+            .collect()
+    }
 
+    /// the argument at `pos` followed by its occurrences in the body, if `pos` is on one of them
+    pub fn get_arg_ranges(&self, pos: &CursorPosition, parser: &Parser) -> Vec<(CursorPosition, CursorPosition)> {
+        let contains = |range: &(CursorPosition, CursorPosition)| range.0 <= *pos && *pos <= range.1;
+        let occurrences = self.get_arg_occurrences(parser);
+        let Some(index) = self.args.iter()
+            .position(|arg| contains(&arg.get_range()))
+            .or_else(|| occurrences.iter().find(|(_, range)| contains(range)).map(|(index, _)| *index))
+        else {
+            return Vec::new();
+        };
 
+        std::iter::once(self.args[index].get_range())
+            .chain(occurrences.into_iter().filter(|(i, _)| *i == index).map(|(_, range)| range))
+            .collect()
+    }
 
-        let mut expr_tokens = Vec::new();
+    /// whether `name` (lowercase) is one of the arguments, which shadow the symbols outside
+    pub fn has_arg(&self, name: &[u8], parser: &Parser) -> bool {
+        self.args.iter().any(|arg| parser.lexer.compare_range(arg, name))
+    }
 
+    pub(crate) fn get_references(&self, name: &[u8], parser: &Parser, references: &mut Vec<(CursorPosition, CursorPosition)>) {
+        if self.has_arg(name, parser) {
+            return;
+        }
         for e in self.body.iter() {
-            e.to_semantic_token(&mut expr_tokens, pre_line, pre_start, parser);
+            e.get_references(name, parser, references);
         }
+    }
 
-        let mut arg_iter = arg_tokens.iter_mut();
-        let mut exp_iter = expr_tokens.iter_mut();
-
-        let mut next_arg_token = arg_iter.next();
-        let mut next_exp_token = exp_iter.next();
-
-        loop {
-            match (next_arg_token, next_exp_token) {
-            (Some(arg), Some(exp)) => {
-                if arg.delta_line < exp.delta_line {
-                    exp.delta_line -= arg.delta_line;
-                    semantic_tokens.push(*arg);
-                    next_arg_token = arg_iter.next();
-                    next_exp_token = Some(exp);
-                    log::debug!("push arg, delta line");
-                }
-                else if arg.delta_start < exp.delta_start {
-                    exp.delta_start -= arg.delta_start;
-                    if arg.length > exp.delta_start { arg.length = exp.delta_start }
-                    semantic_tokens.push(*arg);
-                    next_arg_token = arg_iter.next();
-                    next_exp_token = Some(exp);
-                    log::debug!("push arg, delta start");
-                }
-                else if arg.delta_line > exp.delta_line{
-                    arg.delta_line -= exp.delta_line;
-                    semantic_tokens.push(*exp);
-                    next_exp_token = exp_iter.next();
-                    next_arg_token = Some(arg);
-                    log::debug!("push exp, deltaline");
-                }
-                else {
-                    arg.delta_start -= exp.delta_start;
-                    if exp.length > arg.delta_start { exp.length = arg.delta_start }
-                    semantic_tokens.push(*exp);
-                    next_exp_token = exp_iter.next();
-                    next_arg_token = Some(arg);
-                    log::debug!("push exp, deltastart");
-                }
-                continue;
-            },
-            (Some(arg), None) => {
-                semantic_tokens.push(*arg);
-                next_arg_token = arg_iter.next();
-                next_exp_token = None;
-                log::debug!("push arg, exps exhausted");
-                continue;
-            },
-            (None, Some(exp)) => {
-                semantic_tokens.push(*exp);
-                next_exp_token = exp_iter.next();
-                next_arg_token = None;
-                log::debug!("push exp, args exhausted");
-                continue;
-            },
-            (None, None) => break,
-            }
+    pub fn get_document_symbol(&self, parser: &Parser) -> DocumentSymbol {
+        get_document_symbol(
+            parser.get_element_str(&self.name),
+            Some(parser.get_element_str(&(self.parenopen, self.parenclose + 1))),
+            SymbolKind::FUNCTION,
+            self,
+            &self.name,
+            self.body.iter().filter_map(|e| e.get_document_symbol(parser)).collect(),
+            parser,
+        )
+    }
 
-        }
-    */
+    /// the signature `name(arg1, arg2, ...)` shown while typing `exec, name(`
+    pub fn get_signature(&self, active: u32, parser: &Parser) -> SignatureInformation {
+        get_signature_information(
+            format!("{}(", parser.get_element_str(&self.name)),
+            self.args.iter().map(|arg| (parser.get_element_str(arg), None)),
+            ")",
+            Some(active),
+        )
     }
 
     pub(crate) fn get_problems(&self, problems: &mut Vec<Problem>) {
@@ -246,29 +242,7 @@ impl Macro {
     }
 
     pub(crate) fn get_highlights(&self, pos: &CursorPosition, parser: &Parser) -> Vec<(CursorPosition, CursorPosition)> {
-        
-        let mut arg_tokens: Vec<(CursorPosition, CursorPosition)> = Vec::new();
-        let mut arg_tokens = Vec::new();
-        let start_inner = self.macro_pos.get_range().1;
-        if let Ok(inner_text) = String::from_utf8(parser.get_element_bytes(&(start_inner, self.end))
-            .to_ascii_lowercase()) {
-
-
-            for arg in self.args.iter()
-                .filter_map(|arg| String::from_utf8(parser.get_element_bytes(arg).to_ascii_lowercase()).ok()) {
-                    log::debug!("look for arg {}", arg);
-                    arg_tokens.extend(inner_text.match_indices(&arg).map(|(idx, _)| {
-                        let mut pos0 = start_inner;
-                        let mut pos1 = start_inner;
-                        parser.lexer.advance_cursor(&mut pos0, idx);
-                        parser.lexer.advance_cursor(&mut pos1, idx + arg.len());
-                        (pos0, pos1)
-                    }));
-                }
-        }
-
-        arg_tokens
-
+        self.get_arg_ranges(pos, parser)
     }
 
     pub(crate) fn accept<V: crate::visitor::Visitor>(&self, visitor: &mut V) {
@@ -281,7 +255,23 @@ impl Macro {
 
 impl HasRange for Macro {
     fn get_range(&self) -> (CursorPosition, CursorPosition) {
-        (self.name.get_range().0, self.end)
+        // an unclosed macro ends with its last expression
+        let end = self.end.unwrap_or_else(|| {
+            self.body.last().map_or(self.macro_pos.get_range().1, |e| e.get_range().1)
+        });
+        (self.name.get_range().0, end)
+    }
+}
+
+impl Shift for Macro {
+    fn shift(&mut self, offset: &Offset) {
+        self.name.shift(offset);
+        self.parenopen.shift(offset);
+        self.args.shift(offset);
+        self.parenclose.shift(offset);
+        self.macro_pos.shift(offset);
+        self.body.shift(offset);
+        self.end.shift(offset);
     }
 }
 
@@ -308,4 +298,32 @@ mod tests {
         }
         assert_eq!(parser.get_elements().len(), 1);
     }
+
+    #[test]
+    fn arguments() {
+        let parser = Parser::from_str("m(a, b): macro = {\n beta = a;\n exec, n(b, a2);\n}");
+        let Expression::Macro(m) = &parser.get_elements()[0] else {
+            panic!("should be macro");
+        };
+        let positions = |ranges: &[(CursorPosition, CursorPosition)]| {
+            ranges.iter()
+                .map(|range| (parser.lexer.line(&range.0), parser.lexer.character(&range.0)))
+                .collect::<Vec<_>>()
+        };
+
+        let occurrences = m.get_arg_occurrences(&parser);
+        assert_eq!(occurrences.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [0, 1]);
+        let ranges: Vec<_> = occurrences.iter().map(|(_, range)| *range).collect();
+        assert_eq!(positions(&ranges), [(1, 8), (2, 9)]);
+
+        let a = occurrences[0].1 .0;
+        assert_eq!(positions(&m.get_arg_ranges(&a, &parser)), [(0, 2), (1, 8)]);
+        let beta = parser.lexer.position_at(parser.lexer.absolute(&a) - 5);
+        assert!(m.get_arg_ranges(&beta, &parser).is_empty());
+
+        let mut tokens = Vec::new();
+        m.to_semantic_token(&mut tokens, &mut 0, &mut 0, &parser);
+        let arguments: Vec<_> = tokens.iter().filter(|t| t.token_type == 11).map(|t| t.length).collect();
+        assert_eq!(arguments, [1, 1, 1, 1]);
+    }
 }
@@ -1,15 +1,17 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     error::Error,
     fmt::Display,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use tower_lsp::lsp_types::{Position, SemanticToken, SemanticTokenType, Url};
+use tower_lsp::lsp_types::{SemanticTokenModifier, SemanticTokenType, Url};
 
 use crate::{
-    error::{MadxLsError, UTF8_PARSER_MSG},
-    lexer::{CursorPosition, HasRange, Lexer, Token},
+    error::MadxLsError,
+    lexer::{CursorPosition, GapVec, HasRange, Lexer, Offset, Shift, Token},
 };
 
 pub mod assignment;
@@ -20,6 +22,7 @@ pub mod madexec;
 pub mod madgeneric;
 pub mod madif;
 pub mod madmacro;
+pub mod operator;
 pub mod problem;
 
 pub use assignment::*;
@@ -30,30 +33,86 @@ pub use madexec::*;
 pub use madgeneric::*;
 pub use madif::*;
 pub use madmacro::*;
+pub use operator::*;
 pub use problem::*;
 
 #[derive(Debug)]
 pub struct Parser {
     pub uri: Option<Url>,
     pub lexer: Lexer,
-    elements: Vec<Expression>,
-    pub labels: HashMap<Vec<u8>, usize>,
+    elements: GapVec<Expression>,
+    /// where each element of `elements` was parsed from
+    spans: GapVec<ElementSpan>,
+    /// the starts of the elements that define each (lowercase) name, in order
+    labels: HashMap<Vec<u8>, Vec<CursorPosition>>,
     pub position: usize,
+    /// one past the furthest token looked at while parsing the current element
+    lookahead: AtomicUsize,
     pub includes: Vec<Url>,
-    pub problems: Vec<Problem>,
+}
+
+/// the tokens a top-level element was parsed from, an edit only re-parses the elements that looked
+/// at one of the changed tokens
+#[derive(Debug, Clone)]
+struct ElementSpan {
+    /// where the first token starts
+    start: CursorPosition,
+    /// where the last token ends
+    end: CursorPosition,
+    /// where the furthest token looked at by this or any earlier element ends (it can lag behind
+    /// after an edit, it's never too early), `None` if one of them looked at the end of the text
+    furthest: Option<CursorPosition>,
+    problems: Vec<Problem>,
+    /// the (lowercase) name the element defines
+    label: Option<Vec<u8>>,
+}
+
+impl Shift for ElementSpan {
+    fn shift(&mut self, offset: &Offset) {
+        self.start.shift(offset);
+        self.end.shift(offset);
+        self.furthest.shift(offset);
+        self.problems.shift(offset);
+    }
+}
+
+/// the later of two `ElementSpan::furthest`
+fn later(a: Option<CursorPosition>, b: Option<CursorPosition>) -> Option<CursorPosition> {
+    Some(a?.max(b?))
+}
+
+/// whether `expression` is a `call`, which `Parser::scan_includes` looks at
+fn is_include(expression: &Expression) -> bool {
+    matches!(expression, Expression::MadGeneric(g) if g.match_name == b"call")
 }
 
 pub const LEGEND_TYPE: &[SemanticTokenType] = &[
-    SemanticTokenType::TYPE,      // 0
-    SemanticTokenType::STRING,    // 1
-    SemanticTokenType::COMMENT,   // 2
-    SemanticTokenType::OPERATOR,  // 3
-    SemanticTokenType::FUNCTION,  // 4
-    SemanticTokenType::PARAMETER, // 5
-    SemanticTokenType::MACRO,     // 6
-    SemanticTokenType::NAMESPACE, // 7
-    SemanticTokenType::KEYWORD,   // 8
-    SemanticTokenType::KEYWORD,   // 9
+    SemanticTokenType::TYPE,           // 0
+    SemanticTokenType::STRING,         // 1
+    SemanticTokenType::COMMENT,        // 2
+    SemanticTokenType::OPERATOR,       // 3
+    SemanticTokenType::FUNCTION,       // 4
+    SemanticTokenType::PARAMETER,      // 5
+    SemanticTokenType::MACRO,          // 6
+    SemanticTokenType::NAMESPACE,      // 7
+    SemanticTokenType::KEYWORD,        // 8
+    SemanticTokenType::VARIABLE,       // 9
+    SemanticTokenType::NUMBER,         // 10
+    SemanticTokenType::TYPE_PARAMETER, // 11
+];
+
+/// the bits of `token_modifiers_bitset`, see the constants in [`crate::semantic_tokens`]
+pub const LEGEND_MODIFIER: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,     // 1 << 0
+    SemanticTokenModifier::new("deferred"), // 1 << 1
+    SemanticTokenModifier::READONLY,        // 1 << 2
+    SemanticTokenModifier::DEFAULT_LIBRARY, // 1 << 3
+];
+
+/// the constants madx defines itself
+pub const BUILTIN_CONSTANTS: &[&[u8]] = &[
+    b"pi", b"twopi", b"degrad", b"raddeg", b"e", b"amu0", b"emass", b"mumass", b"nmass", b"pmass",
+    b"clight", b"qelect", b"hbar", b"erad", b"prad",
 ];
 
 impl Parser {
@@ -71,16 +130,15 @@ impl Parser {
     }
 
     pub fn from_lexer(uri: Option<Url>, lexer: Lexer) -> Self {
-        lexer.get_tokens();
-
         let mut parser = Self {
             uri,
             lexer,
-            elements: Vec::new(),
+            elements: GapVec::new(),
+            spans: GapVec::new(),
             labels: HashMap::new(),
             includes: Vec::new(),
             position: 0,
-            problems: Vec::new(),
+            lookahead: AtomicUsize::new(0),
         };
         parser.parse_elements();
         parser.scan_includes();
@@ -98,31 +156,158 @@ impl Parser {
     /// reloads the parser from a given text (as bytes)
     pub fn reload(&mut self, bytes: &[u8]) {
         log::debug!("reloading lexer");
-        log::debug!(
-            "text {}",
-            std::str::from_utf8(bytes).unwrap_or(UTF8_PARSER_MSG)
-        );
+        log::debug!("text {}", String::from_utf8_lossy(bytes));
         self.lexer = Lexer::from_bytes(bytes.to_vec());
         self.position = 0;
-        //log::debug!("lexer: {:#?}", self.lexer);
-        //log::debug!("tokens: {:#?}", self.tokens);
 
         self.elements.clear();
+        self.spans.clear();
         self.labels.clear();
         self.parse_elements();
     }
 
+    /// replaces the bytes `start..end` of the text by `text`.
+    ///
+    /// Only the top-level elements that looked at one of the re-lexed tokens are parsed again.
+    /// Parsing stops as soon as it reaches the first token of an old element behind the edit,
+    /// the elements from there on stay behind the gap as they are.
+    pub fn edit(&mut self, start: usize, end: usize, text: &str) {
+        let edit = self.lexer.edit(start, end, text);
+        self.move_gap(&edit.gap);
+
+        let first = self
+            .spans
+            .partition_point(|s| s.furthest.is_some_and(|f| f <= edit.start));
+        let mut furthest = match first.checked_sub(1) {
+            Some(i) => self.spans[i].furthest,
+            None => Some(CursorPosition::default()),
+        };
+        self.position = match first.checked_sub(1) {
+            Some(i) => {
+                let end = self.spans[i].end;
+                self.lexer
+                    .get_tokens()
+                    .partition_point(|t| t.get_range().0 < end)
+            }
+            None => 0,
+        };
+        self.spans.move_gap(first);
+        self.elements.move_gap(first);
+
+        // the first token behind the re-lexed ones
+        let old_tokens = edit.first + edit.inserted;
+        let mut includes_changed = false;
+        let resynced = loop {
+            let current = self.lexer.get_tokens().get(self.position);
+            if let Some(current) = current.filter(|_| self.position >= old_tokens) {
+                let current = current.get_range().0;
+                while self.spans.behind_gap().is_some_and(|s| s.start < current) {
+                    includes_changed |= self.remove_behind_gap();
+                }
+                if self.spans.behind_gap().is_some_and(|s| s.start == current) {
+                    break true;
+                }
+            }
+            let Some((span, element)) = self.parse_element(furthest) else {
+                break false;
+            };
+            furthest = span.furthest;
+            includes_changed |= is_include(&element);
+            self.push_element(span, element);
+        };
+        if !resynced {
+            while self.spans.behind_gap().is_some() {
+                includes_changed |= self.remove_behind_gap();
+            }
+        }
+
+        for span in self.spans.iter_mut_behind_gap() {
+            match (span.furthest, furthest) {
+                (Some(f), Some(new)) if f < new => span.furthest = furthest,
+                (Some(_), None) => span.furthest = None,
+                _ => break,
+            }
+        }
+
+        if includes_changed {
+            self.scan_includes();
+        }
+    }
+
+    /// moves the positions of the elements along with the gap of the lexer, see `TokenEdit::gap`.
+    /// Only the elements with positions the gap moves over change.
+    fn move_gap(&mut self, gap: &Offset) {
+        if gap.from >= gap.to {
+            return;
+        }
+        let first = self
+            .spans
+            .partition_point(|s| s.furthest.is_some_and(|f| f.resolve(gap).0 < gap.from));
+        let last = self
+            .spans
+            .partition_point(|s| s.start.resolve(gap).0 < gap.to);
+        for index in first..last {
+            let Some(span) = self.spans.get_mut(index) else {
+                break;
+            };
+            let start = span.start;
+            span.shift(gap);
+            if let Some(label) = &span.label {
+                let positions = self.labels.get_mut(label).into_iter().flatten();
+                if let Some(position) = positions.into_iter().find(|p| **p == start) {
+                    *position = span.start;
+                }
+            }
+            if let Some(element) = self.elements.get_mut(index) {
+                element.shift(gap);
+            }
+        }
+    }
+
+    /// moves the gap behind the last element, see `Lexer::close_gap`
+    pub fn close_gap(&mut self) {
+        let gap = self.lexer.close_gap();
+        self.move_gap(&gap);
+        self.spans.move_gap(self.spans.len());
+        self.elements.move_gap(self.elements.len());
+    }
+
+    /// adds an element in front of the gap
+    fn push_element(&mut self, span: ElementSpan, element: Expression) {
+        if let Some(label) = &span.label {
+            let positions = self.labels.entry(label.clone()).or_default();
+            let index = positions.partition_point(|p| *p < span.start);
+            positions.insert(index, span.start);
+        }
+        self.spans.push(span);
+        self.elements.push(element);
+    }
+
+    /// removes the element behind the gap, returns whether it was a `call`
+    fn remove_behind_gap(&mut self) -> bool {
+        if let Some(span) = self.spans.remove_behind_gap() {
+            if let Some(label) = span.label {
+                if let Some(positions) = self.labels.get_mut(&label) {
+                    // the element replacing it can start at the same position
+                    if let Some(index) = positions.iter().position(|p| *p == span.start) {
+                        positions.remove(index);
+                    }
+                    if positions.is_empty() {
+                        self.labels.remove(&label);
+                    }
+                }
+            }
+        }
+        self.elements
+            .remove_behind_gap()
+            .is_some_and(|e| is_include(&e))
+    }
+
     pub fn scan_includes(&mut self) {
         log::info!("scanning includes");
 
         let call_cmds = self.elements.iter().filter_map(|e| match e {
-            Expression::MadGeneric(g) => {
-                if g.match_name == b"call" {
-                    Some(g)
-                } else {
-                    None
-                }
-            }
+            Expression::MadGeneric(g) if is_include(e) => Some(g),
             _ => None,
         });
 
@@ -131,10 +316,13 @@ impl Parser {
         self.includes = call_cmds
             .filter_map(|g| g.args.first()?.value.as_ref())
             .filter_map(|arg| {
-                get_path_relative_to_parent(
-                    self.uri.as_ref(),
-                    self.get_element_bytes(&**arg)[1..].to_vec(),
-                )
+                let path = match arg.as_ref() {
+                    Expression::String(range) => {
+                        self.get_element_bytes(&self.lexer.get_string_contents(range))
+                    }
+                    arg => self.get_element_bytes(arg),
+                };
+                get_path_relative_to_parent(self.uri.as_ref(), path.to_vec())
             })
             .filter_map(|filename| {
                 if let Some(fname) = filename.extension() {
@@ -152,55 +340,96 @@ impl Parser {
     }
 
     fn parse_elements(&mut self) {
-        while let Some(expr) = Assignment::parse(self) {
-            match &expr {
-                Expression::Label(label) => {
-                    self.labels.insert(
-                        self.get_element_bytes(&label.name)
-                            .to_ascii_lowercase()
-                            .to_vec(),
-                        self.elements.len(),
-                    );
-                }
-                Expression::Assignment(assignment) => {
-                    self.labels.insert(
-                        self.get_element_bytes(&*assignment.lhs)
-                            .to_ascii_lowercase()
-                            .to_vec(),
-                        self.elements.len(),
-                    );
-                }
-                Expression::Macro(m) => {
-                    self.labels.insert(
-                        self.get_element_bytes(&m.name)
-                            .to_ascii_lowercase()
-                            .to_vec(),
-                        self.elements.len(),
-                    );
-                }
-                _ => {}
-            }
-            //expr.get_problems(&mut self.problems);
-            self.elements.push(expr);
+        let mut furthest = Some(CursorPosition::default());
+        while let Some((span, element)) = self.parse_element(furthest) {
+            furthest = span.furthest;
+            self.push_element(span, element);
         }
     }
 
+    /// parses the next top-level element and collects its problems, `furthest` is the one of the
+    /// element before it
+    fn parse_element(
+        &mut self,
+        furthest: Option<CursorPosition>,
+    ) -> Option<(ElementSpan, Expression)> {
+        let start = self.position;
+        self.lookahead.store(start, Ordering::Relaxed);
+        let expr = Assignment::parse(self)?;
+
+        let tokens = self.lexer.get_tokens();
+        let looked = self.lookahead.load(Ordering::Relaxed).max(self.position);
+        let token_end = |index: usize| tokens.get(index).map(|t| t.get_range().1);
+        // the checks of the elements misfire on valid MAD-X (list valued parameters, macros
+        // defined in the file including this one), they stay off for now
+        let problems = Vec::new();
+        //expr.get_problems(&mut problems);
+        let span = ElementSpan {
+            start: tokens
+                .get(start)
+                .map(|t| t.get_range().0)
+                .unwrap_or_default(),
+            end: self
+                .position
+                .checked_sub(1)
+                .and_then(token_end)
+                .unwrap_or_default(),
+            furthest: later(furthest, looked.checked_sub(1).and_then(token_end)),
+            problems,
+            label: self.get_label_name(&expr),
+        };
+        Some((span, expr))
+    }
+
+    /// the (lowercase) name `element` defines
+    fn get_label_name(&self, element: &Expression) -> Option<Vec<u8>> {
+        let name = match element {
+            Expression::Label(label) => self.get_element_bytes(&label.name),
+            Expression::Assignment(assignment) => self.get_element_bytes(&*assignment.lhs),
+            Expression::Macro(m) => self.get_element_bytes(&m.name),
+            _ => return None,
+        };
+        Some(name.to_ascii_lowercase())
+    }
+
+    /// the index of the element that defines `name` (lowercase), the last definition wins
+    pub fn get_label_index(&self, name: &[u8]) -> Option<usize> {
+        let start = *self.labels.get(name)?.last()?;
+        Some(self.spans.partition_point(|s| s.start < start))
+    }
+
+    /// the (lowercase) names the elements define
+    pub fn get_label_names(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.labels.keys()
+    }
+
+    pub fn get_problems(&self) -> impl Iterator<Item = &Problem> {
+        self.spans.iter().flat_map(|s| s.problems.iter())
+    }
+
     pub fn uri(&self) -> Option<&Url> {
         self.uri.as_ref()
     }
 
-    pub fn get_elements(&self) -> &Vec<Expression> {
+    pub fn get_elements(&self) -> &GapVec<Expression> {
         &self.elements
     }
 
     pub fn peek_token(&self) -> Option<&Token> {
-        self.lexer.get_tokens().get(self.position)
+        self.get_token(self.position)
     }
 
     pub fn next_token(&mut self) -> Option<&Token> {
         let pos = self.position;
         self.advance();
-        self.lexer.get_tokens().get(pos)
+        self.get_token(pos)
+    }
+
+    /// returns the token at `index`, all token access while parsing has to go through here to keep
+    /// track of the lookahead
+    pub fn get_token(&self, index: usize) -> Option<&Token> {
+        self.lookahead.fetch_max(index + 1, Ordering::Relaxed);
+        self.lexer.get_tokens().get(index)
     }
 
     // ---- cursor movement ------------------------------------------------------------------------
@@ -223,21 +452,42 @@ impl Parser {
 
     // ---- print elements -------------------------------------------------------------------------
     //
-    pub fn get_element_bytes<R: HasRange>(&self, element: &R) -> &[u8] {
+    pub fn get_element_bytes<R: HasRange>(&self, element: &R) -> Cow<'_, [u8]> {
         self.lexer.get_range_bytes(element)
     }
     pub fn get_element_str<R: HasRange>(&self, element: &R) -> String {
-        String::from_utf8(self.lexer.get_range_bytes(element).to_vec())
-            .unwrap_or_else(|_| UTF8_PARSER_MSG.to_string())
+        self.lexer.get_range_str(element).into_owned()
     }
 
-    pub fn get_expression_at(&self, pos: CursorPosition) -> Option<&Expression> {
-        for expr in self.elements.iter().rev() {
-            if expr.get_range().0 <= pos {
-                return Some(expr);
-            }
+    /// returns the token under the cursor, a cursor directly behind a token still counts as on it
+    pub fn get_token_at(&self, pos: CursorPosition) -> Option<&Token> {
+        let tokens = self.lexer.get_tokens();
+        let index = tokens.partition_point(|t| t.get_range().1 <= pos);
+        if let Some(token) = tokens.get(index).filter(|t| t.get_range().0 <= pos) {
+            return Some(token);
+        }
+        let previous = index.checked_sub(1).and_then(|i| tokens.get(i));
+        previous.filter(|t| t.get_range().1 == pos)
+    }
+
+    /// the expressions at `pos`, from the top-level element down to the innermost expression in
+    /// the bodies of macros, ifs, whiles and environments containing it
+    pub fn get_expressions_at(&self, pos: CursorPosition) -> Vec<&Expression> {
+        let mut expressions = Vec::new();
+        let mut current = self.elements.iter().rev().find(|e| e.get_range().0 <= pos);
+        while let Some(expr) = current {
+            expressions.push(expr);
+            current = expr.get_bodies().into_iter().rev().find(|e| {
+                let (start, end) = e.get_range();
+                start <= pos && pos <= end
+            });
         }
-        None
+        expressions
+    }
+
+    /// the innermost expression at `pos`, see `get_expressions_at`
+    pub fn get_expression_at(&self, pos: CursorPosition) -> Option<&Expression> {
+        self.get_expressions_at(pos).pop()
     }
 }
 
@@ -248,28 +498,28 @@ impl Display for Parser {
                 Expression::String(_) => writeln!(
                     f,
                     "String({})",
-                    String::from_utf8_lossy(self.get_element_bytes(expr))
+                    String::from_utf8_lossy(&self.get_element_bytes(expr))
                 )?,
                 Expression::Macro(m) => writeln!(
                     f,
                     "Macro({})",
-                    String::from_utf8_lossy(self.get_element_bytes(m))
+                    String::from_utf8_lossy(&self.get_element_bytes(m))
                 )?,
                 Expression::Comment(_) => writeln!(
                     f,
                     "Comment({})",
-                    String::from_utf8_lossy(self.get_element_bytes(expr))
+                    String::from_utf8_lossy(&self.get_element_bytes(expr))
                 )?,
                 Expression::Symbol(_) => todo!(),
                 Expression::Label(l) => writeln!(
                     f,
                     "Label({})",
-                    String::from_utf8_lossy(self.get_element_bytes(l))
+                    String::from_utf8_lossy(&self.get_element_bytes(l))
                 )?,
                 Expression::Assignment(a) => writeln!(
                     f,
                     "Assignment({})",
-                    String::from_utf8_lossy(self.get_element_bytes(a))
+                    String::from_utf8_lossy(&self.get_element_bytes(a))
                 )?,
                 Expression::MadGeneric(generic) => {
                     write!(
@@ -285,17 +535,25 @@ impl Display for Parser {
                 Expression::MadEnvironment(env) => writeln!(
                     f,
                     "Environment({})",
-                    String::from_utf8_lossy(self.get_element_bytes(env))
+                    String::from_utf8_lossy(&self.get_element_bytes(env))
+                )?,
+                Expression::Operator(_)
+                | Expression::Group(_)
+                | Expression::Call(_)
+                | Expression::Array(_) => writeln!(
+                    f,
+                    "Arithmetic({})",
+                    String::from_utf8_lossy(&self.get_element_bytes(expr))
                 )?,
-                Expression::Operator(_) => todo!(),
                 Expression::TokenExp(_) => writeln!(
                     f,
                     "Token({})",
-                    String::from_utf8_lossy(self.get_element_bytes(expr))
+                    String::from_utf8_lossy(&self.get_element_bytes(expr))
                 )?,
                 Expression::Exit(_) => writeln!(f, "EXIT")?,
                 Expression::Exec(_) => writeln!(f, "exec (??)")?,
                 Expression::If(_) => writeln!(f, "if(...) {{ }}")?,
+                Expression::While(_) => writeln!(f, "while(...) {{ }}")?,
                 Expression::Noop(_) => writeln!(f, "NOOP")?,
             }
         }
@@ -303,6 +561,26 @@ impl Display for Parser {
     }
 }
 
+/// checks whether `name` (lowercase) is a builtin madx command or environment
+pub fn is_builtin(name: &[u8]) -> bool {
+    GENERIC_BUILTINS.contains_key(name) || GENERIC_ENVS.contains_key(name)
+}
+
+/// the words madx reserves for control flow and declarations
+pub const KEYWORDS: &[&[u8]] = &[
+    b"if", b"elseif", b"else", b"while", b"const", b"real", b"int", b"shared",
+];
+
+/// checks whether `name` (lowercase) is a keyword
+pub fn is_keyword(name: &[u8]) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+/// checks whether `name` (lowercase) is a builtin madx constant
+pub fn is_builtin_constant(name: &[u8]) -> bool {
+    BUILTIN_CONSTANTS.contains(&name)
+}
+
 /// we assume that madx scripts are runnable in their respective working directory,
 /// so we search for includes there.
 ///
@@ -313,16 +591,16 @@ impl Display for Parser {
 /// # Params:
 /// * `uri` - the Url of the parent document (.madx script)
 /// * `bytes` - the bytes from `Parser::get_element_bytes()` from the `"call"` `MadGeneric`
-fn get_path_relative_to_parent(uri: Option<&Url>, bytes: Vec<u8>) -> Option<PathBuf> {
+pub(crate) fn get_path_relative_to_parent(uri: Option<&Url>, bytes: Vec<u8>) -> Option<PathBuf> {
     let call_path = String::from_utf8(bytes).ok()?;
     if let Some(uri) = uri {
         let root = uri.to_file_path().ok()?.parent()?.to_path_buf();
         let p = root.join(call_path).canonicalize();
-        println!("{:?}", p);
+        log::debug!("include path: {:?}", p);
         p.ok()
     } else {
         let pb: PathBuf = call_path.into();
-        println!("no base uri: {}", pb.display());
+        log::debug!("no base uri: {}", pb.display());
         pb.canonicalize().ok()
     }
 }
@@ -343,6 +621,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_comment_characters_in_strings() {
+        let parser = Parser::from_str("print, text=\"done!\";\ncall, file='//afs/a.madx';");
+        let values: Vec<_> = parser
+            .get_elements()
+            .iter()
+            .filter_map(|e| match e {
+                Expression::MadGeneric(g) => g.args.first()?.value.as_deref(),
+                _ => None,
+            })
+            .map(|value| match value {
+                Expression::String(range) => parser.get_element_str(range),
+                _ => panic!("expected a string, got {:?}", value),
+            })
+            .collect();
+        assert_eq!(values, ["\"done!\"", "'//afs/a.madx'"]);
+    }
+
     #[test]
     fn parse_option() {
         let parser = Parser::from_str("option, echo, -warn;");
@@ -388,10 +684,124 @@ mod tests {
         }
     }
 
+    #[test]
+    fn builtins() {
+        assert!(is_builtin(b"twiss"));
+        assert!(is_builtin(b"match"));
+        assert!(!is_builtin(b"on_x1"));
+    }
+
     #[test]
     fn parse_empty() {
         let parser = Parser::from_str("");
 
         assert!(parser.get_elements().is_empty());
     }
+
+    #[test]
+    fn expression_at() {
+        let parser =
+            Parser::from_str("m(x): macro = {\n  if (x > 0) {\n    y = x;\n  }\n}\nz = 1;");
+        let at = |line, character| {
+            let pos = parser
+                .lexer
+                .cursor_pos_from_text_pos(tower_lsp::lsp_types::Position::new(line, character));
+            parser
+                .get_expressions_at(pos)
+                .iter()
+                .map(|e| parser.get_element_str(*e))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            at(2, 4),
+            [
+                "m(x): macro = {\n  if (x > 0) {\n    y = x;\n  }\n}",
+                "if (x > 0) {\n    y = x;\n  }",
+                "y = x",
+            ]
+        );
+        // between the statements of a body
+        assert_eq!(at(1, 0).len(), 1);
+        assert_eq!(at(5, 0), ["z = 1"]);
+    }
+
+    /// applies an edit incrementally and compares the result with parsing the edited text
+    fn check_edit(text: &str, start: usize, end: usize, insert: &str) {
+        let mut parser = Parser::from_str(text);
+        parser.edit(start, end, insert);
+        parser.close_gap();
+        let edited = [&text[..start], insert, &text[end..]].concat();
+        let expected = Parser::from_str(&edited);
+
+        let context = format!("{:?} -> {:?}", &text[start..end], insert);
+        assert_eq!(
+            parser.lexer.get_tokens(),
+            expected.lexer.get_tokens(),
+            "{context}"
+        );
+        assert_eq!(
+            parser.lexer.get_text(),
+            expected.lexer.get_text(),
+            "{context}"
+        );
+        assert_eq!(parser.get_elements(), expected.get_elements(), "{context}");
+        let token_spans = |p: &Parser| p.spans.iter().map(|s| (s.start, s.end)).collect::<Vec<_>>();
+        assert_eq!(token_spans(&parser), token_spans(&expected), "{context}");
+        assert_eq!(parser.labels, expected.labels, "{context}");
+        let problems = |p: &Parser| format!("{:?}", p.get_problems().collect::<Vec<_>>());
+        assert_eq!(problems(&parser), problems(&expected), "{context}");
+    }
+
+    #[test]
+    fn edit_statements() {
+        let text = "a = 1;\ntwiss, sequence=lhcb1;\nq1: quadrupole, l=1;\nb = 2;";
+        // change a value
+        check_edit(text, 4, 5, "42");
+        // rename a label
+        check_edit(text, 30, 32, "qf");
+        // insert and remove statements
+        check_edit(text, 6, 6, "\nexec, mk_beam(6.8);");
+        check_edit(text, 0, 30, "");
+        // grow tokens into each other
+        check_edit(text, 2, 3, ":=");
+        check_edit(text, 6, 7, "");
+        // open a macro that swallows the rest of the text
+        check_edit(text, 7, 7, "m(x): macro = {");
+        // comments out the rest
+        check_edit(text, 7, 7, "/*");
+        // at the very end
+        check_edit(text, text.len(), text.len(), "\nc = 3;");
+    }
+
+    #[test]
+    fn edit_in_front_of_incomplete_elements() {
+        // the unfinished elements have no positions that could be taken for the edited ones
+        for text in [
+            "if (a > 1) {\n    b = 2;",
+            "while (a > 1",
+            "m(x): macro = {\n    x = 1;",
+            "exec, m(a;\nexec;",
+            "select, flag=twiss;\nmatch, sequence=lhcb1;",
+        ] {
+            check_edit(text, 0, 0, "c = 3;\n");
+            check_edit(text, 0, 0, " ");
+            check_edit(text, 1, 1, " ");
+        }
+    }
+
+    #[test]
+    fn edit_everywhere() {
+        for text in [
+            include_str!("../../tests/test.madx"),
+            include_str!("../../tests/with_errors.madx"),
+        ] {
+            for start in (0..text.len()).step_by(7) {
+                for insert in ["", "x", ";\n", "{", "}", "!"] {
+                    let end = if insert.is_empty() { start + 1 } else { start };
+                    check_edit(text, start, end.min(text.len()), insert);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,504 @@
+use tower_lsp::lsp_types::SemanticToken;
+
+use crate::{
+    lexer::{CursorPosition, HasRange, Offset, Shift, Token},
+    semantic_tokens::get_range_token,
+};
+
+use super::{Expression, Parser};
+
+/// binding strength of the unary `+` and `-`, between `*` and `^` so that `-a^2` is `-(a^2)`
+const UNARY_PRECEDENCE: u8 = 6;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OpKind {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    /// attribute access, `mq->l`
+    Member,
+}
+
+impl OpKind {
+    fn precedence(self) -> u8 {
+        match self {
+            OpKind::Or => 1,
+            OpKind::And => 2,
+            OpKind::Equal
+            | OpKind::NotEqual
+            | OpKind::Less
+            | OpKind::LessEqual
+            | OpKind::Greater
+            | OpKind::GreaterEqual => 3,
+            OpKind::Add | OpKind::Sub => 4,
+            OpKind::Mul | OpKind::Div => 5,
+            OpKind::Pow => 7,
+            OpKind::Member => 8,
+        }
+    }
+}
+
+/// a unary or binary operation
+#[derive(Debug, PartialEq)]
+pub struct Operator {
+    pub kind: OpKind,
+    /// the range of the operator itself
+    pub op: (CursorPosition, CursorPosition),
+    /// `None` for the unary `+` and `-`
+    pub left: Option<Box<Expression>>,
+    pub right: Box<Expression>,
+}
+
+/// a parenthesised expression, `(a + b)`
+#[derive(Debug, PartialEq)]
+pub struct Group {
+    pub parenopen: CursorPosition,
+    pub inner: Box<Expression>,
+    /// `None` if the group is not closed
+    pub parenclose: Option<CursorPosition>,
+}
+
+/// a function call, `sqrt(x)` or `table(twiss, ip1, betx)`
+#[derive(Debug, PartialEq)]
+pub struct Call {
+    pub name: Token,
+    pub parenopen: CursorPosition,
+    pub args: Vec<Expression>,
+    /// `None` if the argument list is not closed
+    pub parenclose: Option<CursorPosition>,
+}
+
+/// an array literal, `{k0, k1, k2}`
+#[derive(Debug, PartialEq)]
+pub struct Array {
+    pub braceopen: CursorPosition,
+    pub items: Vec<Expression>,
+    /// `None` if the array is not closed
+    pub braceclose: Option<CursorPosition>,
+}
+
+impl Operator {
+    /// parses an arithmetic expression, i.e. the right hand side of an assignment, the value of a
+    /// parameter or the condition of an if. Returns the plain operand if there is no operator
+    pub fn parse(parser: &mut Parser) -> Option<Expression> {
+        Self::parse_binary(parser, 0)
+    }
+
+    /// precedence climbing: parses an operand followed by all operators binding at least as
+    /// strong as `min_precedence`
+    fn parse_binary(parser: &mut Parser, min_precedence: u8) -> Option<Expression> {
+        let mut left = Self::parse_unary(parser)?;
+
+        while let Some((kind, op)) = peek_operator(parser) {
+            let precedence = kind.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            let before = parser.get_position();
+            parser.advance();
+
+            // `^` is right associative, everything else left associative
+            let next_precedence = if kind == OpKind::Pow {
+                precedence
+            } else {
+                precedence + 1
+            };
+            match Self::parse_binary(parser, next_precedence) {
+                Some(right) => {
+                    left = Expression::Operator(Self {
+                        kind,
+                        op,
+                        left: Some(Box::new(left)),
+                        right: Box::new(right),
+                    });
+                }
+                None => {
+                    // a dangling operator is left to whatever comes after the expression
+                    parser.set_position(before);
+                    break;
+                }
+            }
+        }
+        Some(left)
+    }
+
+    fn parse_unary(parser: &mut Parser) -> Option<Expression> {
+        if let Some((kind @ (OpKind::Add | OpKind::Sub), op)) = peek_operator(parser) {
+            let before = parser.get_position();
+            parser.advance();
+            if let Some(right) = Self::parse_binary(parser, UNARY_PRECEDENCE) {
+                return Some(Expression::Operator(Self {
+                    kind,
+                    op,
+                    left: None,
+                    right: Box::new(right),
+                }));
+            }
+            parser.set_position(before);
+            return None;
+        }
+        Self::parse_operand(parser)
+    }
+
+    fn parse_operand(parser: &mut Parser) -> Option<Expression> {
+        let token = parser.peek_token()?.clone();
+        match token {
+            Token::Number(_) => {
+                parser.advance();
+                Some(Expression::TokenExp(token))
+            }
+            Token::String(_) => Expression::parse_string(parser),
+            Token::ParentOpen(parenopen) => {
+                let before = parser.get_position();
+                parser.advance();
+                if let Some(inner) = Self::parse_binary(parser, 0) {
+                    let parenclose = parse_close(parser, |t| matches!(t, Token::ParentClose(_)));
+                    return Some(Expression::Group(Group {
+                        parenopen,
+                        inner: Box::new(inner),
+                        parenclose,
+                    }));
+                }
+                parser.set_position(before);
+                None
+            }
+            Token::BraceOpen(braceopen) => {
+                parser.advance();
+                let (items, braceclose) =
+                    Self::parse_list(parser, |t| matches!(t, Token::BraceClose(_)));
+                Some(Expression::Array(Array {
+                    braceopen,
+                    items,
+                    braceclose,
+                }))
+            }
+            Token::Ident(_) => {
+                parser.advance();
+                if let Some(Token::ParentOpen(parenopen)) = parser.peek_token().cloned() {
+                    parser.advance();
+                    let (args, parenclose) =
+                        Self::parse_list(parser, |t| matches!(t, Token::ParentClose(_)));
+                    return Some(Expression::Call(Call {
+                        name: token,
+                        parenopen,
+                        args,
+                        parenclose,
+                    }));
+                }
+                Some(Expression::TokenExp(token))
+            }
+            _ => None,
+        }
+    }
+
+    /// parses a comma separated list up to and including the closing token, stops at the first
+    /// thing that is neither an expression nor a comma
+    fn parse_list(
+        parser: &mut Parser,
+        is_close: fn(&Token) -> bool,
+    ) -> (Vec<Expression>, Option<CursorPosition>) {
+        let mut items = Vec::new();
+        loop {
+            if let Some(close) = parse_close(parser, is_close) {
+                return (items, Some(close));
+            }
+            if !items.is_empty() {
+                if let Some(Token::Komma(_)) = parser.peek_token() {
+                    parser.advance();
+                } else {
+                    return (items, None);
+                }
+            }
+            match Self::parse_binary(parser, 0) {
+                Some(item) => items.push(item),
+                None => return (items, None),
+            }
+        }
+    }
+
+    pub fn to_semantic_token(
+        &self,
+        semantic_tokens: &mut Vec<SemanticToken>,
+        pre_line: &mut u32,
+        pre_start: &mut u32,
+        parser: &Parser,
+    ) {
+        if let Some(left) = &self.left {
+            left.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
+        }
+        semantic_tokens.push(get_range_token(&self.op, 3, pre_line, pre_start, parser));
+        match (self.kind, self.right.as_ref()) {
+            (OpKind::Member, Expression::TokenExp(attribute)) => {
+                semantic_tokens.push(get_range_token(attribute, 5, pre_line, pre_start, parser));
+            }
+            (_, right) => right.to_semantic_token(semantic_tokens, pre_line, pre_start, parser),
+        }
+    }
+}
+
+impl Call {
+    pub fn to_semantic_token(
+        &self,
+        semantic_tokens: &mut Vec<SemanticToken>,
+        pre_line: &mut u32,
+        pre_start: &mut u32,
+        parser: &Parser,
+    ) {
+        semantic_tokens.push(get_range_token(&self.name, 4, pre_line, pre_start, parser));
+        for arg in self.args.iter() {
+            arg.to_semantic_token(semantic_tokens, pre_line, pre_start, parser);
+        }
+    }
+}
+
+/// the operator at the parser's position and its range
+fn peek_operator(parser: &Parser) -> Option<(OpKind, (CursorPosition, CursorPosition))> {
+    let token = parser.peek_token()?;
+    let kind = match token {
+        Token::Operator(p) => match parser.lexer.get_token_byte(p) {
+            b'+' => OpKind::Add,
+            b'-' => OpKind::Sub,
+            b'*' => OpKind::Mul,
+            _ => return None,
+        },
+        Token::Slash(_) => OpKind::Div,
+        Token::Caret(_) => OpKind::Pow,
+        Token::Arrow(_) => OpKind::Member,
+        Token::DoubleEqual(_) => OpKind::Equal,
+        Token::NotEqual(_) => OpKind::NotEqual,
+        Token::Less(_) => OpKind::Less,
+        Token::LessEqual(_) => OpKind::LessEqual,
+        Token::Greater(_) => OpKind::Greater,
+        Token::GreaterEqual(_) => OpKind::GreaterEqual,
+        Token::And(_) => OpKind::And,
+        Token::Or(_) => OpKind::Or,
+        _ => return None,
+    };
+    Some((kind, token.get_range()))
+}
+
+/// consumes the closing token of a group, call or array if it is next
+fn parse_close(parser: &mut Parser, is_close: fn(&Token) -> bool) -> Option<CursorPosition> {
+    let close = parser.peek_token().filter(|t| is_close(t))?.get_range().0;
+    parser.advance();
+    Some(close)
+}
+
+impl HasRange for Operator {
+    fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        let start = self
+            .left
+            .as_ref()
+            .map(|left| left.get_range().0)
+            .unwrap_or(self.op.0);
+        (start, self.right.get_range().1)
+    }
+}
+
+impl HasRange for Group {
+    fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        let end = self
+            .parenclose
+            .map(|p| p + 1)
+            .unwrap_or_else(|| self.inner.get_range().1);
+        (self.parenopen, end)
+    }
+}
+
+impl HasRange for Call {
+    fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        let end = match (self.parenclose, self.args.last()) {
+            (Some(p), _) => p + 1,
+            (None, Some(last)) => last.get_range().1,
+            (None, None) => self.parenopen + 1,
+        };
+        (self.name.get_range().0, end)
+    }
+}
+
+impl HasRange for Array {
+    fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        let end = match (self.braceclose, self.items.last()) {
+            (Some(p), _) => p + 1,
+            (None, Some(last)) => last.get_range().1,
+            (None, None) => self.braceopen + 1,
+        };
+        (self.braceopen, end)
+    }
+}
+
+impl Shift for Operator {
+    fn shift(&mut self, offset: &Offset) {
+        self.op.shift(offset);
+        self.left.shift(offset);
+        self.right.shift(offset);
+    }
+}
+
+impl Shift for Group {
+    fn shift(&mut self, offset: &Offset) {
+        self.parenopen.shift(offset);
+        self.inner.shift(offset);
+        self.parenclose.shift(offset);
+    }
+}
+
+impl Shift for Call {
+    fn shift(&mut self, offset: &Offset) {
+        self.name.shift(offset);
+        self.parenopen.shift(offset);
+        self.args.shift(offset);
+        self.parenclose.shift(offset);
+    }
+}
+
+impl Shift for Array {
+    fn shift(&mut self, offset: &Offset) {
+        self.braceopen.shift(offset);
+        self.items.shift(offset);
+        self.braceclose.shift(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// prints an arithmetic expression fully parenthesised
+    fn render(expr: &Expression, parser: &Parser) -> String {
+        let list = |items: &[Expression]| {
+            items
+                .iter()
+                .map(|e| render(e, parser))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match expr {
+            Expression::Operator(op) => match &op.left {
+                Some(left) => format!(
+                    "({} {} {})",
+                    render(left, parser),
+                    parser.get_element_str(&op.op),
+                    render(&op.right, parser)
+                ),
+                None => format!(
+                    "({}{})",
+                    parser.get_element_str(&op.op),
+                    render(&op.right, parser)
+                ),
+            },
+            Expression::Group(g) => format!("[{}]", render(&g.inner, parser)),
+            Expression::Call(c) => {
+                format!("{}<{}>", parser.get_element_str(&c.name), list(&c.args))
+            }
+            Expression::Array(a) => format!("{{{}}}", list(&a.items)),
+            _ => parser.get_element_str(expr),
+        }
+    }
+
+    fn parse_rhs(text: &str) -> (String, String) {
+        let parser = Parser::from_str(text);
+        let rhs = match parser.get_elements().first() {
+            Some(Expression::Assignment(a)) => a.rhs.as_ref().unwrap(),
+            other => panic!("expected an assignment, got {:?}", other),
+        };
+        (render(rhs, &parser), parser.get_element_str(rhs.as_ref()))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parse_rhs("a = 1 + 2 * 3 - 4;").0, "((1 + (2 * 3)) - 4)");
+        assert_eq!(parse_rhs("a = -b^2^c;").0, "(-(b ^ (2 ^ c)))");
+        assert_eq!(parse_rhs("a = 2 * -b / c;").0, "((2 * (-b)) / c)");
+        assert_eq!(parse_rhs("a = (b + c) * d;").0, "([(b + c)] * d)");
+        assert_eq!(
+            parse_rhs("a = x > 1 && y <= 2 || z;").0,
+            "(((x > 1) && (y <= 2)) || z)"
+        );
+        assert_eq!(
+            parse_rhs("a = b <> c && d != e;").0,
+            "((b <> c) && (d != e))"
+        );
+    }
+
+    #[test]
+    fn calls_attributes_and_arrays() {
+        assert_eq!(
+            parse_rhs("a := sqrt(mq->l) * table(twiss, ip1, betx);"),
+            (
+                "(sqrt<(mq -> l)> * table<twiss, ip1, betx>)".to_string(),
+                "sqrt(mq->l) * table(twiss, ip1, betx)".to_string()
+            )
+        );
+        assert_eq!(parse_rhs("a = {k0, -k1, 2*k2};").0, "{k0, (-k1), (2 * k2)}");
+        assert_eq!(parse_rhs("a = {};").0, "{}");
+    }
+
+    #[test]
+    fn incomplete() {
+        // the dangling operator is not part of the expression
+        assert_eq!(parse_rhs("a = b + ;"), ("b".to_string(), "b".to_string()));
+        // unclosed groups end at their last operand
+        assert_eq!(parse_rhs("a = (b * c;").1, "(b * c");
+        assert_eq!(parse_rhs("a = f(b, ;").1, "f(b");
+    }
+
+    #[test]
+    fn parameter_values_and_conditions() {
+        let parser =
+            Parser::from_str("mq: quadrupole, l = 2 * lq, k1 := kq / l;\nif (a + 1 > b) { }");
+        let elements = parser.get_elements();
+
+        let Expression::Label(label) = &elements[0] else {
+            panic!("expected a label")
+        };
+        let values: Vec<_> = label
+            .command
+            .args
+            .iter()
+            .map(|arg| render(arg.value.as_ref().unwrap(), &parser))
+            .collect();
+        assert_eq!(values, ["(2 * lq)", "(kq / l)"]);
+
+        let Expression::If(if_object) = &elements[2] else {
+            panic!("expected an if")
+        };
+        assert_eq!(if_object.condition.len(), 1);
+        assert_eq!(render(&if_object.condition[0], &parser), "((a + 1) > b)");
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let parser = Parser::from_str("a = sqrt(mq->l) + 2;");
+        let mut tokens = Vec::new();
+        let (mut pre_line, mut pre_start) = (0, 0);
+        for e in parser.get_elements() {
+            e.to_semantic_token(&mut tokens, &mut pre_line, &mut pre_start, &parser);
+        }
+
+        let types: Vec<_> = tokens.iter().map(|t| (t.token_type, t.length)).collect();
+        assert_eq!(
+            types,
+            [
+                (9, 1),
+                (3, 1),
+                (4, 4),
+                (9, 2),
+                (3, 2),
+                (5, 1),
+                (3, 1),
+                (10, 1)
+            ]
+        );
+    }
+}
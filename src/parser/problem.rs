@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use tower_lsp::lsp_types::Range;
 
-use crate::lexer::CursorPosition;
+use crate::lexer::{CursorPosition, Offset, Shift};
 
 #[derive(Debug, Clone)]
 pub struct MaybeProblem {
@@ -24,10 +24,42 @@ impl Display for Problem {
         match self {
             Problem::MissingCallee(_,_) => write!(f, "Missing Macro, check includes"),
             Problem::InvalidParam(_) => write!(f, "Invalid Mad Parameter"),
-            Problem::Error(_, _, _) => todo!(),
-            Problem::Warning(_, _, _) => todo!(),
-            Problem::Hint(_, _, _) => todo!(),
+            Problem::Error(msg, _, _) => write!(f, "{}", msg),
+            Problem::Warning(msg, _, _) => write!(f, "{}", msg),
+            Problem::Hint(msg, _, _) => write!(f, "{}", msg),
         }
     }
 }
 
+impl Problem {
+    pub fn get_range(&self) -> (CursorPosition, CursorPosition) {
+        match self {
+            Problem::MissingCallee(_, range) => *range,
+            Problem::InvalidParam(range) => *range,
+            Problem::Error(_, start, end) => (*start, *end),
+            Problem::Warning(_, start, end) => (*start, *end),
+            Problem::Hint(_, start, end) => (*start, *end),
+        }
+    }
+}
+
+impl Shift for Problem {
+    fn shift(&mut self, offset: &Offset) {
+        match self {
+            Problem::MissingCallee(_, range) => range.shift(offset),
+            Problem::InvalidParam(range) => range.shift(offset),
+            Problem::Error(_, start, end) => {
+                start.shift(offset);
+                end.shift(offset);
+            }
+            Problem::Warning(_, start, end) => {
+                start.shift(offset);
+                end.shift(offset);
+            }
+            Problem::Hint(_, start, end) => {
+                start.shift(offset);
+                end.shift(offset);
+            }
+        }
+    }
+}
@@ -38,14 +38,14 @@ impl<'a> Visitor for UndefinedExecCall<'a> {
     fn visit_exec(&mut self, exec_exp: &crate::parser::MadExec) {
         let callee = exec_exp.get_callee();
         let callee_str = self.parser.get_element_bytes(&callee);
-        println!("Checking callee: {}", String::from_utf8_lossy(callee_str));
+        println!("Checking callee: {}", String::from_utf8_lossy(&callee_str));
         println!("against: {:?}", self.labels);
-        self.check(callee_str, exec_exp.get_range().0, exec_exp.get_range().1);
+        self.check(&callee_str, exec_exp.get_range().0, exec_exp.get_range().1);
     }
     fn visit_label(&mut self, label: &crate::parser::Label) {
         println!("Checking label: {}", self.parser.get_element_str(&label.name));
         let label_str = self.parser.get_element_bytes(&label.name.get_range());
-        if !self.labels.iter().any(|l| l == label_str) {
+        if !self.labels.iter().any(|l| **l == *label_str) {
             self.labels.push(label_str.to_vec());
         }
     }
@@ -54,6 +54,8 @@ impl<'a> Visitor for UndefinedExecCall<'a> {
         
     }
 
+    fn visit_while(&mut self, _while_exp: &crate::parser::While) {}
+
     fn visit_generic(&mut self, generic: &crate::parser::MadGeneric) {
         
     }
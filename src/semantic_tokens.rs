@@ -1,15 +1,35 @@
-use tower_lsp::lsp_types::SemanticToken;
+use tower_lsp::lsp_types::{SemanticToken, SemanticTokensEdit};
 
-use crate::{lexer::HasRange, parser::Parser};
+use crate::{
+    lexer::{CursorPosition, HasRange, Token},
+    parser::{is_builtin_constant, is_keyword, Parser},
+};
+
+// ---- modifier bits, in the order of `LEGEND_MODIFIER` --------------------------------------------
+pub const DECLARATION: u32 = 1 << 0;
+pub const DEFERRED: u32 = 1 << 1;
+pub const READONLY: u32 = 1 << 2;
+pub const DEFAULT_LIBRARY: u32 = 1 << 3;
 
 pub fn get_range_token<R: HasRange>( token: &R, token_type: u32, pline: &mut u32,
                                      pstart: &mut u32, parser: &Parser) -> SemanticToken {
+    get_modified_range_token(token, token_type, 0, pline, pstart, parser)
+}
 
+/// like [`get_range_token`], with the `modifiers` bitset set
+pub fn get_modified_range_token<R: HasRange>(
+    token: &R,
+    token_type: u32,
+    modifiers: u32,
+    pline: &mut u32,
+    pstart: &mut u32,
+    parser: &Parser,
+) -> SemanticToken {
     let range = token.get_range();
-    let line = range.0.line() as u32;
-    let start = range.0.character(parser.lexer.lines()) as u32;
+    let line = parser.lexer.line(&range.0) as u32;
+    let start = parser.lexer.character(&range.0) as u32;
     let delta_line = line - *pline;
-    let length = (range.1.absolute() - range.0.absolute()) as u32;
+    let length = (parser.lexer.absolute(&range.1) - parser.lexer.absolute(&range.0)) as u32;
     let delta_start = if delta_line == 0 {
         start - *pstart
     }
@@ -23,7 +43,7 @@ pub fn get_range_token<R: HasRange>( token: &R, token_type: u32, pline: &mut u32
         delta_start,
         length,
         token_type,
-        token_modifiers_bitset: 0
+        token_modifiers_bitset: modifiers,
     };
 
     *pline = line;
@@ -32,3 +52,188 @@ pub fn get_range_token<R: HasRange>( token: &R, token_type: u32, pline: &mut u32
     token
 }
 
+
+/// pushes the semantic tokens of a single lexer token, punctuation doesn't get any
+pub fn push_token(
+    token: &Token,
+    semantic_tokens: &mut Vec<SemanticToken>,
+    pline: &mut u32,
+    pstart: &mut u32,
+    parser: &Parser,
+) {
+    let semantic_token = match token {
+        Token::Comment(range) => get_range_token(range, 2, pline, pstart, parser),
+        Token::MultilineComment(lines) => {
+            for range in lines.iter() {
+                semantic_tokens.push(get_range_token(range, 2, pline, pstart, parser));
+            }
+            return;
+        }
+        Token::Ident(range) => {
+            let name = parser.get_element_bytes(range).to_ascii_lowercase();
+            if is_keyword(&name) {
+                get_range_token(range, 8, pline, pstart, parser)
+            } else if is_builtin_constant(&name) {
+                let modifiers = READONLY | DEFAULT_LIBRARY;
+                get_modified_range_token(range, 9, modifiers, pline, pstart, parser)
+            } else {
+                get_range_token(range, 9, pline, pstart, parser)
+            }
+        }
+        Token::String(range) => get_range_token(range, 1, pline, pstart, parser),
+        Token::Number(range) => get_range_token(range, 10, pline, pstart, parser),
+        Token::Operator(_)
+        | Token::Slash(_)
+        | Token::Caret(_)
+        | Token::Less(_)
+        | Token::Greater(_)
+        | Token::LessEqual(_)
+        | Token::GreaterEqual(_)
+        | Token::NotEqual(_)
+        | Token::And(_)
+        | Token::Or(_)
+        | Token::Arrow(_)
+        | Token::Equal(_)
+        | Token::DoubleEqual(_)
+        | Token::ColonEqual(_) => get_range_token(token, 3, pline, pstart, parser),
+        _ => return,
+    };
+    semantic_tokens.push(semantic_token);
+}
+
+/// sets `token_type` on the tokens in `tokens` (encoded relative to `previous`) that start at one
+/// of the `ranges`, and adds tokens for the ranges not covered yet. Returns the tokens, still
+/// relative to `previous`, together with the line and start of the last one.
+pub fn set_token_type(
+    tokens: &[SemanticToken],
+    previous: (u32, u32),
+    ranges: &[(CursorPosition, CursorPosition)],
+    token_type: u32,
+    parser: &Parser,
+) -> (Vec<SemanticToken>, u32, u32) {
+    let (mut line, mut start) = previous;
+    let mut absolute = Vec::with_capacity(tokens.len() + ranges.len());
+    for token in tokens {
+        if token.delta_line > 0 {
+            line += token.delta_line;
+            start = token.delta_start;
+        } else {
+            start += token.delta_start;
+        }
+        absolute.push((line, start, *token));
+    }
+
+    for range in ranges {
+        let line = parser.lexer.line(&range.0) as u32;
+        let start = parser.lexer.character(&range.0) as u32;
+        match absolute.binary_search_by_key(&(line, start), |(l, s, _)| (*l, *s)) {
+            Ok(index) => absolute[index].2.token_type = token_type,
+            Err(index) => {
+                // ranges inside a longer token (e.g. a string) keep the type of that token
+                if index > 0 {
+                    let (l, s, token) = absolute[index - 1];
+                    if l == line && s + token.length > start {
+                        continue;
+                    }
+                }
+                let token = SemanticToken {
+                    delta_line: 0,
+                    delta_start: 0,
+                    length: (parser.lexer.absolute(&range.1) - parser.lexer.absolute(&range.0))
+                        as u32,
+                    token_type,
+                    token_modifiers_bitset: 0,
+                };
+                absolute.insert(index, (line, start, token));
+            }
+        }
+    }
+
+    let (mut pline, mut pstart) = previous;
+    let tokens = absolute
+        .into_iter()
+        .map(|(line, start, token)| {
+            let delta_line = line - pline;
+            let delta_start = if delta_line == 0 { start - pstart } else { start };
+            pline = line;
+            pstart = start;
+            SemanticToken {
+                delta_line,
+                delta_start,
+                ..token
+            }
+        })
+        .collect();
+    (tokens, pline, pstart)
+}
+
+/// the edits turning `previous` into `tokens`, a single edit replacing everything between the
+/// common start and the common end
+pub fn get_semantic_token_edits(
+    previous: &[SemanticToken],
+    tokens: &[SemanticToken],
+) -> Vec<SemanticTokensEdit> {
+    let start = previous
+        .iter()
+        .zip(tokens)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let end = previous[start..]
+        .iter()
+        .rev()
+        .zip(tokens[start..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let deleted = previous.len() - start - end;
+    let inserted = &tokens[start..tokens.len() - end];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+
+    // the edit positions count the integers of the encoded array, five per token
+    vec![SemanticTokensEdit {
+        start: 5 * start as u32,
+        delete_count: 5 * deleted as u32,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(delta_line: u32, length: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start: 0,
+            length,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    #[test]
+    fn edits_replace_the_changed_middle() {
+        let previous = [token(0, 3), token(1, 4), token(1, 5), token(2, 6)];
+        let tokens = [token(0, 3), token(1, 7), token(1, 8), token(1, 5), token(2, 6)];
+
+        assert_eq!(get_semantic_token_edits(&previous, &previous), vec![]);
+        assert_eq!(
+            get_semantic_token_edits(&previous, &tokens),
+            vec![SemanticTokensEdit {
+                start: 5,
+                delete_count: 5,
+                data: Some(vec![token(1, 7), token(1, 8)]),
+            }]
+        );
+        assert_eq!(
+            get_semantic_token_edits(&tokens, &previous[..1]),
+            vec![SemanticTokensEdit {
+                start: 5,
+                delete_count: 20,
+                data: Some(vec![]),
+            }]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use tower_lsp::lsp_types::{
+    Documentation, ParameterInformation, ParameterLabel, SignatureInformation,
+};
+
+use crate::{
+    lexer::{CursorPosition, HasRange, Token},
+    parser::{Parser, GENERIC_BUILTINS},
+};
+
+/// the call the cursor is currently in
+#[derive(Debug, PartialEq, Eq)]
+pub enum CallContext {
+    /// `exec, name(a, |`, together with the index of the active argument
+    Macro { name: Vec<u8>, active: u32 },
+    /// `twiss, sequence=lhcb1, |`, together with the attribute that is currently being typed
+    Builtin {
+        name: Vec<u8>,
+        attribute: Option<Vec<u8>>,
+    },
+}
+
+/// finds the call the cursor is in by looking at the tokens of the current statement up to `pos`.
+/// Names and attributes are returned lowercase.
+pub fn get_call_context(parser: &Parser, pos: CursorPosition) -> Option<CallContext> {
+    let tokens = parser.lexer.get_tokens();
+    let end = tokens.partition_point(|t| t.get_range().0 < pos);
+    let start = (0..end)
+        .rev()
+        .find(|i| {
+            matches!(
+                tokens[*i],
+                Token::SemiColon(_) | Token::BraceOpen(_) | Token::BraceClose(_)
+            )
+        })
+        .map_or(0, |i| i + 1);
+    let statement = (start..end)
+        .map(|i| &tokens[i])
+        .filter(|t| !matches!(t, Token::Comment(_) | Token::MultilineComment(_)))
+        .collect::<Vec<_>>();
+    let lowercase = |token: &Token| parser.get_element_bytes(token).to_ascii_lowercase();
+
+    if let [exec, Token::Komma(_), name @ Token::Ident(_), Token::ParentOpen(_), args @ ..] =
+        statement.as_slice()
+    {
+        if parser.lexer.compare_range(*exec, b"exec") {
+            let mut depth = 0;
+            let mut active = 0;
+            for arg in args {
+                match arg {
+                    Token::ParentOpen(_) => depth += 1,
+                    // the call has already been closed
+                    Token::ParentClose(_) if depth == 0 => return None,
+                    Token::ParentClose(_) => depth -= 1,
+                    Token::Komma(_) if depth == 0 => active += 1,
+                    _ => {}
+                }
+            }
+            return Some(CallContext::Macro {
+                name: lowercase(name),
+                active,
+            });
+        }
+    }
+
+    // builtins, optionally preceded by a label
+    let command = match statement.as_slice() {
+        [Token::Ident(_), Token::Colon(_), command @ ..] => command,
+        command => command,
+    };
+    let [name @ Token::Ident(_), Token::Komma(_), args @ ..] = command else {
+        return None;
+    };
+    let name = lowercase(name);
+    if !GENERIC_BUILTINS.contains_key(name.as_slice()) {
+        return None;
+    }
+    let current = args
+        .iter()
+        .rposition(|t| matches!(t, Token::Komma(_)))
+        .map_or(0, |i| i + 1);
+    let attribute = match &args[current..] {
+        [Token::Operator(_), attribute @ Token::Ident(_), ..]
+        | [attribute @ Token::Ident(_), ..] => Some(lowercase(attribute)),
+        _ => None,
+    };
+    Some(CallContext::Builtin { name, attribute })
+}
+
+/// builds a signature `prefix` + `params` joined by `", "` + `suffix`, the parameters are
+/// referenced by their offsets in the label
+pub fn get_signature_information<I: IntoIterator<Item = (String, Option<String>)>>(
+    prefix: String,
+    params: I,
+    suffix: &str,
+    active_parameter: Option<u32>,
+) -> SignatureInformation {
+    let mut label = prefix;
+    let mut parameters = Vec::new();
+    for (i, (param, documentation)) in params.into_iter().enumerate() {
+        if i > 0 {
+            label.push_str(", ");
+        }
+        let start = label.encode_utf16().count() as u32;
+        label.push_str(&param);
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, label.encode_utf16().count() as u32]),
+            documentation: documentation.map(Documentation::String),
+        });
+    }
+    label.push_str(suffix);
+
+    SignatureInformation {
+        label,
+        documentation: None,
+        parameters: Some(parameters),
+        active_parameter,
+    }
+}
@@ -0,0 +1,60 @@
+use tower_lsp::lsp_types::{Range, SymbolKind, TypeHierarchyItem, Url};
+
+use crate::parser::{Label, MadParam, Parser, GENERIC_BUILTINS};
+
+/// the type hierarchy item of the element defined by `label`
+pub fn get_element_item(label: &Label, parser: &Parser, uri: &Url) -> TypeHierarchyItem {
+    TypeHierarchyItem {
+        name: parser.get_element_str(&label.name),
+        kind: SymbolKind::CLASS,
+        tags: None,
+        detail: Some(parser.get_element_str(&label.command.name)),
+        uri: uri.clone(),
+        range: parser.lexer.range_to_text_range(label),
+        selection_range: parser.lexer.range_to_text_range(&label.name),
+        data: None,
+    }
+}
+
+/// the type hierarchy item of a builtin class. Builtins aren't defined in any file, the item
+/// points to where it's used instead.
+pub fn get_builtin_item(name: String, uri: &Url, range: Range) -> TypeHierarchyItem {
+    TypeHierarchyItem {
+        name,
+        kind: SymbolKind::CLASS,
+        tags: None,
+        detail: Some("builtin".to_string()),
+        uri: uri.clone(),
+        range,
+        selection_range: range,
+        data: None,
+    }
+}
+
+/// the attributes of a builtin class, or `None` if `name` (lowercase) is no builtin
+pub fn get_builtin_attributes(name: &[u8]) -> Option<Vec<String>> {
+    Some(
+        GENERIC_BUILTINS
+            .get(name)?
+            .match_params
+            .iter()
+            .map(|(param, _)| String::from_utf8_lossy(param).to_string())
+            .collect(),
+    )
+}
+
+/// the attributes as they are set in an element definition, e.g. `k1:=kq1`
+pub fn get_attributes(args: &[MadParam], parser: &Parser) -> Vec<String> {
+    args.iter()
+        .map(|arg| parser.get_element_str(&arg.get_range()))
+        .collect()
+}
+
+/// formats the attributes inherited from each ancestor for the hover
+pub fn format_inherited_attributes(ancestors: &[(String, Vec<String>)]) -> String {
+    let mut text = String::from("**inherits**\n");
+    for (ancestor, attributes) in ancestors {
+        text.push_str(&format!("- `{}`: {}\n", ancestor, attributes.join(", ")));
+    }
+    text
+}
@@ -7,6 +7,7 @@ pub trait Visitor {
     fn visit_exec(&mut self, exec_exp: &MadExec);
     fn visit_label(&mut self, label: &Label);
     fn visit_if(&mut self, if_exp: &crate::parser::If);
+    fn visit_while(&mut self, while_exp: &crate::parser::While);
     fn visit_generic(&mut self, generic: &crate::parser::MadGeneric);
 }
 
@@ -51,6 +52,9 @@ impl<'a> Visitor for PrintVisitor<'a> {
     fn visit_if(&mut self, if_exp: &crate::parser::If) {
         writeln!(self.buffer, "if").unwrap();
     }
+    fn visit_while(&mut self, _while_exp: &crate::parser::While) {
+        writeln!(self.buffer, "while").unwrap();
+    }
     fn visit_generic(&mut self, generic: &crate::parser::MadGeneric) {
         print_token_exp(&generic.name, self);
     }